@@ -0,0 +1,8 @@
+-- Add a status column to track the double opt-in flow.
+-- Subscribers that signed up before the confirmation flow existed
+-- are considered confirmed.
+ALTER TABLE subscriptions ADD COLUMN status TEXT NULL;
+UPDATE subscriptions
+    SET status = 'confirmed'
+    WHERE status IS NULL;
+ALTER TABLE subscriptions ALTER COLUMN status SET NOT NULL;
@@ -0,0 +1,8 @@
+-- Seed an initial admin user so that the publishing endpoints can be used.
+-- The password (`everythinghastostartsomewhere`) must be changed after the first deploy.
+INSERT INTO users (user_id, username, password_hash)
+VALUES (
+    'ddf8994f-d522-4659-8d02-c1d479057be6',
+    'admin',
+    '$argon2id$v=19$m=15000,t=2,p=1$umH+Tdv603ZjP7hLKljQsQ$KPmVlc/Dy1d1m6jxKfQcX7E2eWSyCV8GUcWJtyjeaHE'
+);
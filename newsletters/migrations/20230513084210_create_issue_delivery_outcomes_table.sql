@@ -0,0 +1,13 @@
+-- Create Issue Delivery Outcomes Table
+-- final outcome of each (issue, subscriber) pair once it leaves the queue
+CREATE TABLE issue_delivery_outcomes (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    -- 'delivered', 'failed' or 'skipped'
+    outcome TEXT NOT NULL,
+    n_attempts SMALLINT NOT NULL,
+    last_error TEXT NULL,
+    recorded_at timestamptz NOT NULL,
+    PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
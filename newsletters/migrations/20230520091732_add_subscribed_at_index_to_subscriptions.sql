@@ -0,0 +1,4 @@
+-- Add Subscribed At Index To Subscriptions
+-- keyset pagination of the admin listing walks the table
+-- in (subscribed_at, id) order
+CREATE INDEX subscriptions_subscribed_at_id_idx ON subscriptions (subscribed_at, id);
@@ -0,0 +1,49 @@
+-- Create Lists and List Memberships Tables
+-- an installation can run several newsletters, each with its own
+-- subscribers: the double opt-in status now lives on the membership
+CREATE TABLE lists (
+    list_id uuid NOT NULL,
+    -- identifier used in urls and request bodies, it never changes
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (list_id)
+);
+
+-- the implicit list everybody subscribed to so far,
+-- requests that do not name a list still target it
+INSERT INTO lists (list_id, slug, name, created_at)
+VALUES ('6c4e1d3a-6d47-4c1b-9a34-0d6f1c2b9e57', 'newsletter', 'Our newsletter', now());
+
+CREATE TABLE list_memberships (
+    list_id uuid NOT NULL
+        REFERENCES lists (list_id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    -- 'pending_confirmation', 'confirmed' or 'unsubscribed'
+    status TEXT NOT NULL,
+    subscribed_at timestamptz NOT NULL,
+    PRIMARY KEY (list_id, subscriber_id)
+);
+-- keyset pagination of the admin listing, within a list
+CREATE INDEX list_memberships_subscribed_at_idx
+    ON list_memberships (list_id, subscribed_at, subscriber_id);
+
+INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+SELECT '6c4e1d3a-6d47-4c1b-9a34-0d6f1c2b9e57', id, status, subscribed_at
+FROM subscriptions;
+ALTER TABLE subscriptions DROP COLUMN status;
+-- superseded by the index on the memberships
+DROP INDEX subscriptions_subscribed_at_id_idx;
+
+-- a confirmation link confirms a single membership
+ALTER TABLE subscription_tokens ADD COLUMN list_id uuid NULL
+    REFERENCES lists (list_id);
+UPDATE subscription_tokens SET list_id = '6c4e1d3a-6d47-4c1b-9a34-0d6f1c2b9e57';
+ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+
+-- issues are delivered to the confirmed members of their list
+ALTER TABLE newsletter_issues ADD COLUMN list_id uuid NULL
+    REFERENCES lists (list_id);
+UPDATE newsletter_issues SET list_id = '6c4e1d3a-6d47-4c1b-9a34-0d6f1c2b9e57';
+ALTER TABLE newsletter_issues ALTER COLUMN list_id SET NOT NULL;
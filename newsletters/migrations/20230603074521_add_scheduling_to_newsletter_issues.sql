@@ -0,0 +1,15 @@
+-- Add Scheduling To Newsletter Issues
+-- an issue stays 'scheduled' until the scheduler queues its deliveries,
+-- it is 'enqueued' from then on, unless an admin 'cancelled' it before
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NULL;
+UPDATE newsletter_issues SET status = 'enqueued';
+ALTER TABLE newsletter_issues ALTER COLUMN status SET NOT NULL;
+-- NULL for issues sent right away
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_at timestamptz NULL;
+-- when the deliveries were queued, unknown until then
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+
+-- the scheduler only ever looks for due issues
+CREATE INDEX newsletter_issues_scheduled_at_idx
+    ON newsletter_issues (scheduled_at)
+    WHERE status = 'scheduled';
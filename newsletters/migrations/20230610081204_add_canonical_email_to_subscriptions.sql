@@ -0,0 +1,47 @@
+-- Add Canonical Email To Subscriptions
+-- addresses are told apart by their canonical form, the one typed by
+-- the subscriber stays in `email` and is used to display and send
+ALTER TABLE subscriptions ADD COLUMN email_canonical TEXT NULL;
+-- close enough for the existing rows: domains converted to punycode
+-- only matter for the addresses collected from now on
+UPDATE subscriptions SET email_canonical = lower(normalize(btrim(email), NFC));
+
+-- the same person may have signed up twice with different cases:
+-- the oldest subscriber is kept, and inherits the memberships
+-- and tokens of the others
+CREATE TEMPORARY TABLE duplicate_subscribers AS
+SELECT id, first_value(id) OVER w AS kept_id
+FROM subscriptions
+WINDOW w AS (PARTITION BY email_canonical ORDER BY subscribed_at, id);
+DELETE FROM duplicate_subscribers WHERE id = kept_id;
+
+-- when several of them were members of the same list, an opt-out
+-- wins over a confirmation, which wins over a pending one
+INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+SELECT DISTINCT ON (d.kept_id, m.list_id) m.list_id, d.kept_id, m.status, m.subscribed_at
+FROM list_memberships m
+JOIN duplicate_subscribers d ON d.id = m.subscriber_id
+ORDER BY
+    d.kept_id,
+    m.list_id,
+    array_position(ARRAY['pending_confirmation', 'confirmed', 'unsubscribed'], m.status) DESC,
+    m.subscribed_at
+ON CONFLICT (list_id, subscriber_id) DO UPDATE
+SET status = EXCLUDED.status
+WHERE
+    array_position(ARRAY['pending_confirmation', 'confirmed', 'unsubscribed'], EXCLUDED.status) >
+    array_position(ARRAY['pending_confirmation', 'confirmed', 'unsubscribed'], list_memberships.status);
+DELETE FROM list_memberships
+WHERE subscriber_id IN (SELECT id FROM duplicate_subscribers);
+UPDATE subscription_tokens t
+SET subscriber_id = d.kept_id
+FROM duplicate_subscribers d
+WHERE t.subscriber_id = d.id;
+DELETE FROM subscriptions
+WHERE id IN (SELECT id FROM duplicate_subscribers);
+DROP TABLE duplicate_subscribers;
+
+ALTER TABLE subscriptions ALTER COLUMN email_canonical SET NOT NULL;
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_email_canonical_key UNIQUE (email_canonical);
+-- implied by the canonical form being unique
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
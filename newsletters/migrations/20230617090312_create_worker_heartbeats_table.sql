@@ -0,0 +1,11 @@
+-- Create Worker Heartbeats Table
+-- every background loop writes down regularly that it is still alive,
+-- the readiness probe of the API looks at the most recent one of each kind
+CREATE TABLE worker_heartbeats(
+    worker_id uuid NOT NULL,
+    kind TEXT NOT NULL,
+    started_at timestamptz NOT NULL,
+    last_beat_at timestamptz NOT NULL,
+    PRIMARY KEY (worker_id)
+);
+CREATE INDEX worker_heartbeats_kind_idx ON worker_heartbeats (kind, last_beat_at);
@@ -0,0 +1,28 @@
+-- Remove Seeded Admin
+-- accounts can be told to pick a new password when they next log in,
+-- e.g. after being created or reset from the command line
+ALTER TABLE users ADD COLUMN must_change_password BOOLEAN NOT NULL DEFAULT false;
+
+-- the admin account seeded by an earlier migration has a password anybody
+-- can read in our history: it is dropped unless its password was changed,
+-- the first admin is now created with `zero2prod create-admin`
+-- its sessions go first, a stolen cookie must not outlive the account;
+-- the session state stores every value JSON-encoded, quotes included
+DELETE FROM sessions
+WHERE state->>'user_id' = '"ddf8994f-d522-4659-8d02-c1d479057be6"' AND EXISTS (
+    SELECT 1 FROM users
+    WHERE
+        user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6' AND
+        password_hash = '$argon2id$v=19$m=15000,t=2,p=1$umH+Tdv603ZjP7hLKljQsQ$KPmVlc/Dy1d1m6jxKfQcX7E2eWSyCV8GUcWJtyjeaHE'
+);
+DELETE FROM idempotency
+WHERE user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6' AND EXISTS (
+    SELECT 1 FROM users
+    WHERE
+        user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6' AND
+        password_hash = '$argon2id$v=19$m=15000,t=2,p=1$umH+Tdv603ZjP7hLKljQsQ$KPmVlc/Dy1d1m6jxKfQcX7E2eWSyCV8GUcWJtyjeaHE'
+);
+DELETE FROM users
+WHERE
+    user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6' AND
+    password_hash = '$argon2id$v=19$m=15000,t=2,p=1$umH+Tdv603ZjP7hLKljQsQ$KPmVlc/Dy1d1m6jxKfQcX7E2eWSyCV8GUcWJtyjeaHE';
@@ -0,0 +1,11 @@
+-- Create Confirmation Email Queue Table
+-- confirmation emails sent by the delivery worker rather than right away,
+-- e.g. for the subscribers imported as pending confirmation; revoking a
+-- token drops its email if it was not sent yet
+CREATE TABLE confirmation_email_queue (
+    subscription_token TEXT NOT NULL
+        REFERENCES subscription_tokens (subscription_token) ON DELETE CASCADE,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY(subscription_token)
+);
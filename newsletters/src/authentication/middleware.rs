@@ -0,0 +1,169 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage, HttpResponse};
+use anyhow::Context;
+use base64::Engine;
+use secrecy::Secret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{must_change_password, validate_credentials, AuthError, Credentials};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+
+/// Id of the user that issued the current request.
+///
+/// It is only available to handlers behind an authentication middleware.
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::ops::Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/*
+    middleware guarding the API endpoints with HTTP Basic authentication
+
+    the id of the authenticated user is stored in the request
+    extensions, handlers can retrieve it with `web::ReqData<UserId>`
+ */
+pub async fn reject_invalid_basic_credentials(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let credentials = basic_authentication(req.headers())
+        .map_err(|e| unauthorized(AuthError::InvalidCredentials(e)))?;
+
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .expect("the connection pool must be registered as application data")
+        .clone();
+    let user_id = validate_credentials(credentials, &pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => unauthorized(e),
+            AuthError::UnexpectedError(_) => e500(e)
+        })?;
+    // the password was handed to them by somebody else, it cannot be trusted yet;
+    // the user may also have been deleted since their credentials were checked
+    if must_change_password(user_id, &pool).await.map_err(e500)? != Some(false) {
+        let e = anyhow::anyhow!("the user must change their password first");
+        return Err(unauthorized(AuthError::InvalidCredentials(e)));
+    }
+
+    req.extensions_mut().insert(UserId(user_id));
+    next.call(req).await
+}
+
+// the only pages available to users who must change their password
+const PASSWORD_CHANGE_PATHS: [&str; 2] = ["/admin/password", "/admin/logout"];
+
+/*
+    middleware guarding the `/admin` pages, anonymous visitors
+    are sent back to the login form, users who must change their
+    password to the form to do so
+
+    a session outliving its user, e.g. one deleted by a migration,
+    is purged: whoever holds the cookie is anonymous again
+
+    the id of the logged-in user is stored in the request extensions,
+    handlers can retrieve it with `web::ReqData<UserId>`
+ */
+pub async fn reject_anonymous_users<B: MessageBody>(
+    mut req: ServiceRequest,
+    next: Next<B>
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+
+    let user_id = match session.get_user_id().map_err(e500)? {
+        Some(user_id) => user_id,
+        None => {
+            let response = see_other("/login");
+            let e = anyhow::anyhow!("the user has not logged in");
+            return Err(InternalError::from_response(e, response).into());
+        }
+    };
+
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .expect("the connection pool must be registered as application data");
+    match must_change_password(user_id, pool).await.map_err(e500)? {
+        // the session middleware only saves the purge along a successful response
+        None => {
+            tracing::warn!(%user_id, "the user of the session no longer exists");
+            session.log_out();
+            return Ok(req.into_response(see_other("/login")).map_into_right_body());
+        }
+        Some(true) if !PASSWORD_CHANGE_PATHS.contains(&req.path()) => {
+            let response = see_other("/admin/password");
+            let e = anyhow::anyhow!("the user must change their password first");
+            return Err(InternalError::from_response(e, response).into());
+        }
+        Some(_) => {}
+    }
+
+    req.extensions_mut().insert(UserId(user_id));
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+/*
+    401 response asking the client to authenticate, as required by RFC 7235
+ */
+fn unauthorized(e: AuthError) -> actix_web::Error {
+    let response = HttpResponse::Unauthorized()
+        .insert_header((
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Basic realm="publish""#)
+        ))
+        .finish();
+    InternalError::from_response(e, response).into()
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    // the header value, if present, must be a valid UTF8 string
+    let header_value = headers
+        .get("Authorization")
+        .context("the 'Authorization' header was missing")?
+        .to_str()
+        .context("the 'Authorization' header was not a valid UTF8 string")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("the authorization scheme was not 'Basic'")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("failed to base64-decode 'Basic' credentials")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("the decoded credential string is not valid UTF8")?;
+
+    // split into two segments, using ':' as delimiter
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("a username must be provided in 'Basic' auth"))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("a password must be provided in 'Basic' auth"))?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: Secret::new(password)
+    })
+}
@@ -0,0 +1,8 @@
+mod middleware;
+mod password;
+
+pub use middleware::{reject_anonymous_users, reject_invalid_basic_credentials, UserId};
+pub use password::{
+    change_password, create_admin, must_change_password, validate_credentials, AuthError, Credentials,
+    PASSWORD_LENGTH
+};
\ No newline at end of file
@@ -0,0 +1,211 @@
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::telemetry::spawn_blocking_with_tracing;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error)
+}
+
+/*
+    check the credentials against the Argon2id hash stored for the user
+    and return the id of the user they belong to
+ */
+#[tracing::instrument(
+    name = "Validate credentials",
+    skip(credentials, pool)
+)]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    // we verify against a fallback hash when the user does not exist,
+    // so that the response time does not leak which usernames are valid
+    let mut expected_password_hash = Secret::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            .to_string()
+    );
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool).await?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    // hashing is CPU-bound, it must not stall the actix workers
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("failed to spawn blocking task")??;
+
+    // only reached if the password matched, which cannot happen with the fallback hash
+    user_id
+        .ok_or_else(|| anyhow::anyhow!("unknown username"))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("failed to parse hash in PHC string format")?;
+
+    // the algorithm and its parameters are read from the PHC string
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash
+        )
+        .context("invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(
+    name = "Get stored credentials",
+    skip(username, pool)
+)]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool
+) -> Result<Option<(Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id, password_hash
+        FROM users
+        WHERE username = $1
+        "#,
+        username
+        )
+        .fetch_optional(pool)
+        .await
+        .context("failed to perform a query to retrieve stored credentials")?
+        .map(|row| (row.user_id, Secret::new(row.password_hash)));
+
+    Ok(row)
+}
+
+/*
+    hash a new password with the parameters of the hashes we store,
+    in a PHC string embedding the salt and the parameters
+ */
+fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap()
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+
+    Ok(Secret::new(password_hash))
+}
+
+/// Bounds of the passwords users can choose.
+pub const PASSWORD_LENGTH: std::ops::RangeInclusive<usize> = 12..=128;
+
+#[tracing::instrument(name = "Change password", skip(password, pool))]
+pub async fn change_password(
+    user_id: Uuid,
+    password: Secret<String>,
+    pool: &PgPool
+) -> Result<(), anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("failed to hash the password")?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET
+            password_hash = $1,
+            must_change_password = false
+        WHERE user_id = $2
+        "#,
+        password_hash.expose_secret(),
+        user_id
+        )
+        .execute(pool)
+        .await
+        .context("failed to change the user's password in the database")?;
+
+    Ok(())
+}
+
+/*
+    create an admin account, or reset the password of an existing one
+
+    the password was chosen by whoever ran the command, it may have been
+    seen in a shell history: the admin has to pick their own when they
+    first log in
+ */
+#[tracing::instrument(name = "Create admin", skip(password, pool))]
+pub async fn create_admin(
+    username: &str,
+    password: Secret<String>,
+    pool: &PgPool
+) -> Result<Uuid, anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("failed to hash the password")?;
+
+    let user_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, must_change_password)
+        VALUES ($1, $2, $3, true)
+        ON CONFLICT (username) DO UPDATE
+        SET
+            password_hash = EXCLUDED.password_hash,
+            must_change_password = true
+        RETURNING user_id
+        "#,
+        Uuid::new_v4(),
+        username,
+        password_hash.expose_secret()
+        )
+        .fetch_one(pool)
+        .await
+        .context("failed to store the admin in the database")?;
+
+    Ok(user_id)
+}
+
+/*
+    whether the user must pick a new password before going any further,
+    `None` if there is no such user (anymore)
+ */
+#[tracing::instrument(name = "Check if the password must be changed", skip(pool))]
+pub async fn must_change_password(user_id: Uuid, pool: &PgPool) -> Result<Option<bool>, anyhow::Error> {
+    let must_change_password = sqlx::query_scalar!(
+        "SELECT must_change_password FROM users WHERE user_id = $1",
+        user_id
+        )
+        .fetch_optional(pool)
+        .await
+        .context("failed to check whether the user must change their password")?;
+
+    Ok(must_change_password)
+}
@@ -2,13 +2,32 @@ use secrecy::{Secret, ExposeSecret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::{postgres::{PgConnectOptions, PgSslMode}, ConnectOptions};
 
-#[derive(serde::Deserialize)]
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailTransport, HttpTransport, SmtpTls, SmtpTransport};
+use crate::domain_policy::{DomainPolicy, DomainPolicyMode, DomainSources};
+use crate::issue_rendering::IssueRenderer;
+use crate::rate_limiting::{RateLimiter, RouteRateLimit, TrustedProxy};
+use crate::signup_protection::SignupProtection;
+
+#[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
-    pub application: ApplicationSettings
+    pub application: ApplicationSettings,
+    pub email_client: EmailClientSettings,
+    pub worker: WorkerSettings,
+    #[serde(default)]
+    pub issues: IssueSettings,
+    #[serde(default)]
+    pub rate_limiting: RateLimitSettings,
+    #[serde(default)]
+    pub signup_protection: SignupProtectionSettings,
+    #[serde(default)]
+    pub domain_policy: DomainPolicySettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone)]
 pub struct DatabaseSettings {
     pub username: String,
     pub password: Secret<String>,
@@ -19,11 +38,230 @@ pub struct DatabaseSettings {
     pub require_ssl: bool
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, Clone)]
 pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
-    pub host: String
+    pub host: String,
+    // public url used to build links sent to subscribers
+    pub base_url: String,
+    // key used to sign cookies and unsubscribe links, at least 64 bytes long
+    pub hmac_secret: Secret<String>
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailClientSettings {
+    pub sender_email: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+    // which transport delivers our emails, with its own settings
+    pub transport: EmailTransportSettings
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum EmailTransportSettings {
+    // REST API of a Postmark-style email delivery provider
+    Http(HttpTransportSettings),
+    // relay through an SMTP server
+    Smtp(SmtpSettings)
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct HttpTransportSettings {
+    pub base_url: String,
+    pub authorization_token: Secret<String>
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    // both or neither: relays trusting our network do not ask for credentials
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub tls: SmtpTls
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct WorkerSettings {
+    // a delivery is given up after this many failed attempts
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: i16,
+    // delay before the first retry, doubled after every attempt
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_backoff_milliseconds: u64
+}
+
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct IssueSettings {
+    // HTML template wrapping the issues written in Markdown,
+    // with `{{title}}` and `{{content}}` placeholders
+    pub layout_path: Option<String>
+}
+
+#[derive(serde::Deserialize, Clone, Default)]
+pub struct RateLimitSettings {
+    // routes throttled per client address, the others are not
+    #[serde(default)]
+    pub routes: Vec<RouteRateLimit>,
+    // reverse proxies allowed to tell us the client address
+    // with `X-Forwarded-For`, none by default
+    #[serde(default)]
+    pub trusted_proxies: Vec<TrustedProxy>
+}
+
+impl RateLimitSettings {
+    pub fn limiter(&self) -> Result<RateLimiter, String> {
+        RateLimiter::new(self.routes.clone(), self.trusted_proxies.clone())
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct SignupProtectionSettings {
+    // signup forms submitted faster than this are the work of bots
+    pub min_fill_seconds: i64,
+    // form tokens older than this are not accepted anymore
+    pub max_form_age_hours: i64,
+    // reject signups that do not carry a form token at all
+    pub require_form_token: bool
+}
+
+impl Default for SignupProtectionSettings {
+    fn default() -> Self {
+        Self {
+            min_fill_seconds: 3,
+            max_form_age_hours: 24,
+            require_form_token: false
+        }
+    }
+}
+
+impl SignupProtectionSettings {
+    pub fn protection(&self) -> SignupProtection {
+        SignupProtection::new(
+            chrono::Duration::seconds(self.min_fill_seconds),
+            chrono::Duration::hours(self.max_form_age_hours),
+            self.require_form_token
+        )
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct DomainPolicySettings {
+    pub mode: DomainPolicyMode,
+    pub domains: Vec<String>,
+    // file with one domain per line, added to `domains`
+    pub domains_path: Option<String>,
+    pub block_disposable: bool,
+    // replaces the disposable domains bundled with the application
+    pub disposable_domains_path: Option<String>,
+    // how often the files are checked for changes
+    pub reload_interval_seconds: u64
+}
+
+impl Default for DomainPolicySettings {
+    fn default() -> Self {
+        Self {
+            mode: DomainPolicyMode::default(),
+            domains: Vec::new(),
+            domains_path: None,
+            block_disposable: true,
+            disposable_domains_path: None,
+            reload_interval_seconds: 30
+        }
+    }
+}
+
+impl DomainPolicySettings {
+    pub fn policy(&self) -> Result<DomainPolicy, String> {
+        let sources = DomainSources {
+            mode: self.mode,
+            domains: self.domains.clone(),
+            domains_path: self.domains_path.as_ref().map(Into::into),
+            block_disposable: self.block_disposable,
+            disposable_domains_path: self.disposable_domains_path.as_ref().map(Into::into)
+        };
+        DomainPolicy::new(sources, std::time::Duration::from_secs(self.reload_interval_seconds))
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct MetricsSettings {
+    // `/metrics` is only served on this address, never next to the API,
+    // so that it can be kept out of reach of the public: the loopback
+    // interface unless the scraper runs on another host
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub admin_port: u16
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".into(),
+            admin_port: 9000
+        }
+    }
+}
+
+impl IssueSettings {
+    pub fn renderer(&self) -> Result<IssueRenderer, String> {
+        match &self.layout_path {
+            Some(path) => {
+                let layout = std::fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read the issue layout {}: {}", path, e))?;
+                IssueRenderer::new(&layout)
+            }
+            None => Ok(IssueRenderer::default())
+        }
+    }
+}
+
+impl EmailClientSettings {
+    pub fn sender(&self) -> Result<SubscriberEmail, String> {
+        SubscriberEmail::parse(self.sender_email.clone())
+            .map_err(|e| format!("invalid sender email: {}", e))
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
+
+    pub fn client(self) -> Result<EmailClient, String> {
+        let sender_email = self.sender()?;
+        let timeout = self.timeout();
+
+        let transport = match self.transport {
+            EmailTransportSettings::Http(http) => EmailTransport::Http(HttpTransport::new(
+                http.base_url,
+                http.authorization_token,
+                timeout
+            )),
+            EmailTransportSettings::Smtp(smtp) => {
+                let credentials = match (smtp.username, smtp.password) {
+                    (Some(username), Some(password)) => Some((username, password)),
+                    (None, None) => None,
+                    _ => return Err("the smtp username and password must be set together".into())
+                };
+                let transport = SmtpTransport::new(
+                    &smtp.host,
+                    smtp.port,
+                    smtp.tls,
+                    credentials,
+                    timeout
+                )
+                .map_err(|e| format!("invalid smtp configuration: {}", e))?;
+                EmailTransport::Smtp(transport)
+            }
+        };
+
+        Ok(EmailClient::new(sender_email, transport))
+    }
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -97,7 +335,7 @@ impl DatabaseSettings {
             .host(&self.host)
             .port(self.port)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .ssl_mode(ssl_mode)
     }
     pub fn with_db(&self) -> PgConnectOptions {
@@ -106,4 +344,79 @@ impl DatabaseSettings {
         options
         
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::{EmailClientSettings, EmailTransportSettings, MetricsSettings, WorkerSettings};
+
+    fn settings(transport: serde_json::Value) -> EmailClientSettings {
+        serde_json::from_value(serde_json::json!({
+            "sender_email": "newsletter@example.com",
+            "timeout_milliseconds": 10000,
+            "transport": transport
+        }))
+        .unwrap()
+    }
+
+    // the SMTP connection pool is spawned on the runtime
+    #[tokio::test]
+    async fn the_smtp_transport_needs_none_of_the_http_settings() {
+        let settings = settings(serde_json::json!({
+            "kind": "smtp",
+            "host": "smtp.internal",
+            "port": "25",
+            "tls": "none"
+        }));
+
+        assert!(matches!(settings.transport, EmailTransportSettings::Smtp(_)));
+        assert!(settings.client().is_ok());
+    }
+
+    #[test]
+    fn smtp_credentials_must_be_set_together() {
+        let settings = settings(serde_json::json!({
+            "kind": "smtp",
+            "host": "smtp.internal",
+            "port": 587,
+            "tls": "starttls",
+            "username": "newsletter"
+        }));
+
+        assert!(settings.client().is_err());
+    }
+
+    #[test]
+    fn the_http_transport_needs_none_of_the_smtp_settings() {
+        let settings = settings(serde_json::json!({
+            "kind": "http",
+            "base_url": "https://api.postmarkapp.com",
+            "authorization_token": "my-secret-token"
+        }));
+
+        assert!(matches!(settings.transport, EmailTransportSettings::Http(_)));
+    }
+
+    #[test]
+    fn metrics_are_only_served_on_the_loopback_interface_by_default() {
+        // environment variables always come as strings
+        let settings: MetricsSettings = serde_json::from_value(serde_json::json!({
+            "admin_port": "9100"
+        }))
+        .unwrap();
+
+        assert_eq!(settings.host, "127.0.0.1");
+        assert_eq!(settings.admin_port, 9100);
+    }
+
+    #[test]
+    fn the_worker_settings_can_be_set_from_environment_variables() {
+        let settings: WorkerSettings = serde_json::from_value(serde_json::json!({
+            "max_attempts": "5",
+            "base_backoff_milliseconds": "1000"
+        }))
+        .unwrap();
+
+        assert_eq!(settings.max_attempts, 5);
+        assert_eq!(settings.base_backoff_milliseconds, 1000);
+    }
+}
@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::postgres::types::PgInterval;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::configuration::WorkerSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::ExecutionOutcome;
+use crate::mailing_list::get_list;
+use crate::routes::send_confirmation_email;
+use crate::startup::ApplicationBaseUrl;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+/*
+    queue a confirmation email for each of these tokens, they are
+    sent by the delivery worker once the transaction is committed
+ */
+#[tracing::instrument(skip_all, fields(n_emails = subscription_tokens.len()))]
+pub async fn enqueue_confirmation_emails(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_tokens: &[String]
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_queue (subscription_token)
+        SELECT * FROM UNNEST($1::text[])
+        "#,
+        subscription_tokens
+        )
+        .execute(transaction)
+        .await?;
+
+    Ok(())
+}
+
+/*
+    pick a single confirmation email from the queue and try to send it
+
+    like issue deliveries, a failed attempt is retried with a backoff
+    up to `max_attempts` times; the address can still sign up again
+    to get a fresh link if every attempt failed
+ */
+#[tracing::instrument(
+    skip_all,
+    fields(
+        subscriber_email = tracing::field::Empty,
+        attempt = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_send_confirmation_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &WorkerSettings,
+    base_url: &ApplicationBaseUrl
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_task(pool).await?;
+    if task.is_none() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let (transaction, task) = task.unwrap();
+    let attempt = task.n_retries + 1;
+    Span::current()
+        .record("subscriber_email", display(&task.subscriber_email))
+        .record("attempt", attempt);
+
+    // somebody who confirmed or left in the meantime needs no link
+    if task.status != "pending_confirmation" {
+        tracing::info!(status = %task.status, "skipping a subscriber who is no longer pending confirmation");
+        delete_task(transaction, &task).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    let subscriber_email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => email,
+        Err(e) => {
+            tracing::warn!(error.message = %e, "skipping a subscriber with an invalid stored email");
+            delete_task(transaction, &task).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    let outcome = async {
+        let list = get_list(pool, task.list_id).await.context("failed to fetch the mailing list")?;
+        send_confirmation_email(email_client, &subscriber_email, &list, &base_url.0, &task.subscription_token)
+            .await
+            .context("failed to send the confirmation email")
+    }
+    .await;
+    match outcome {
+        Ok(()) => delete_task(transaction, &task).await?,
+        Err(e) if attempt < settings.max_attempts => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "failed to send a confirmation email. retrying later"
+            );
+            reschedule_task(transaction, &task, settings.backoff(attempt)).await?;
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "failed to send a confirmation email. giving up"
+            );
+            delete_task(transaction, &task).await?;
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct Task {
+    subscription_token: String,
+    n_retries: i16,
+    list_id: Uuid,
+    subscriber_email: String,
+    status: String
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT q.subscription_token, q.n_retries, t.list_id, s.email as subscriber_email, m.status
+        FROM confirmation_email_queue q
+        JOIN subscription_tokens t ON t.subscription_token = q.subscription_token
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        JOIN list_memberships m ON m.subscriber_id = t.subscriber_id AND m.list_id = t.list_id
+        WHERE q.execute_after <= now()
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1
+        "#
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(mut transaction: PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM confirmation_email_queue
+        WHERE subscription_token = $1
+        "#,
+        task.subscription_token
+        )
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    mut transaction: PgTransaction,
+    task: &Task,
+    execute_after: Duration
+) -> Result<(), anyhow::Error> {
+    let execute_after = PgInterval::try_from(execute_after)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + $2
+        WHERE subscription_token = $1
+        "#,
+        task.subscription_token,
+        execute_after
+        )
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
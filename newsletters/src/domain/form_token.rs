@@ -0,0 +1,113 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+/*
+    token embedded in signup forms when they are rendered: the time
+    it was issued followed by an HMAC of that time, so that a bot
+    cannot backdate it to look like a patient human
+
+    nothing is stored, the signature is checked against our secret
+ */
+#[derive(Debug)]
+pub struct FormToken {
+    issued_at: DateTime<Utc>,
+    token: String
+}
+
+impl FormToken {
+    pub fn generate(issued_at: DateTime<Utc>, hmac_secret: &Secret<String>) -> Self {
+        let timestamp = issued_at.timestamp();
+        let signature = URL_SAFE_NO_PAD.encode(signature(timestamp, hmac_secret));
+        Self {
+            // the signature covers whole seconds only
+            issued_at: Utc.timestamp_opt(timestamp, 0).unwrap(),
+            token: format!("{}.{}", timestamp, signature)
+        }
+    }
+
+    pub fn parse(s: String, hmac_secret: &Secret<String>) -> Result<FormToken, String> {
+        let malformed = || "the form token is malformed.".to_string();
+        let (timestamp, signature) = s.split_once('.').ok_or_else(malformed)?;
+        let timestamp: i64 = timestamp.parse().map_err(|_| malformed())?;
+        let issued_at = Utc.timestamp_opt(timestamp, 0).single().ok_or_else(malformed)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| malformed())?;
+
+        // constant-time comparison, as for the unsubscribe tokens
+        mac(timestamp, hmac_secret)
+            .verify_slice(&signature)
+            .map_err(|_| "the form token signature is invalid.".to_string())?;
+
+        Ok(Self { issued_at, token: s })
+    }
+
+    pub fn issued_at(&self) -> DateTime<Utc> {
+        self.issued_at
+    }
+}
+
+impl AsRef<str> for FormToken {
+    fn as_ref(&self) -> &str {
+        &self.token
+    }
+}
+
+fn mac(timestamp: i64, hmac_secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(b"signup-form:");
+    mac.update(&timestamp.to_be_bytes());
+    mac
+}
+
+fn signature(timestamp: i64, hmac_secret: &Secret<String>) -> Vec<u8> {
+    mac(timestamp, hmac_secret).finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FormToken;
+    use chrono::{Duration, Utc};
+    use secrecy::Secret;
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-long-and-secret-random-key-needed-to-verify-message-integrity".into())
+    }
+
+    #[test]
+    fn a_generated_token_is_accepted() {
+        let token = FormToken::generate(Utc::now(), &secret());
+
+        let parsed = FormToken::parse(token.as_ref().to_owned(), &secret()).unwrap();
+
+        assert_eq!(parsed.issued_at(), token.issued_at());
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = FormToken::generate(Utc::now(), &Secret::new("another-secret".into()));
+
+        assert!(FormToken::parse(token.as_ref().to_owned(), &secret()).is_err());
+    }
+
+    #[test]
+    fn a_backdated_token_is_rejected() {
+        let token = FormToken::generate(Utc::now(), &secret());
+        let signature = token.as_ref().split_once('.').unwrap().1;
+        let backdated = (Utc::now() - Duration::hours(1)).timestamp();
+
+        assert!(FormToken::parse(format!("{}.{}", backdated, signature), &secret()).is_err());
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let malformed = ["", "1685000000", "1685000000.", "yesterday.c2lnbmF0dXJl", "1685000000.not-base64!"];
+
+        for token in malformed {
+            assert!(FormToken::parse(token.to_string(), &secret()).is_err(), "{}", token);
+        }
+    }
+}
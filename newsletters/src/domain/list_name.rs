@@ -0,0 +1,59 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Human readable name of a mailing list, shown to subscribers.
+#[derive(Debug, Clone)]
+pub struct ListName(String);
+
+/// Why a list name was rejected.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ListNameError {
+    #[error("the name cannot be empty.")]
+    Empty,
+    #[error("the name cannot be longer than {max_length} characters.")]
+    TooLong { max_length: usize }
+}
+
+impl ListName {
+    const MAX_LENGTH: usize = 128;
+
+    pub fn parse(s: String) -> Result<ListName, ListNameError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ListNameError::Empty);
+        }
+        if s.graphemes(true).count() > Self::MAX_LENGTH {
+            return Err(ListNameError::TooLong { max_length: Self::MAX_LENGTH });
+        }
+
+        Ok(ListName(s.to_owned()))
+    }
+}
+
+impl AsRef<str> for ListName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{ListName, ListNameError};
+
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        assert_eq!(ListName::parse("  Weekly digest ".into()).unwrap().as_ref(), "Weekly digest");
+    }
+
+    #[test]
+    fn whitespace_only_names_are_rejected() {
+        assert_eq!(ListName::parse(" ".into()).err(), Some(ListNameError::Empty));
+    }
+
+    #[test]
+    fn a_name_longer_than_128_graphemes_is_rejected() {
+        assert_eq!(
+            ListName::parse("a".repeat(129)).err(),
+            Some(ListNameError::TooLong { max_length: 128 })
+        );
+    }
+}
@@ -0,0 +1,93 @@
+/*
+    identifier of a mailing list, as used in urls and request bodies
+
+    lowercase letters, digits and dashes only, so that it never
+    needs to be escaped anywhere it ends up
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListSlug(String);
+
+/// Why a list slug was rejected.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ListSlugError {
+    #[error("the slug cannot be empty.")]
+    Empty,
+    #[error("the slug cannot be longer than {max_length} characters.")]
+    TooLong { max_length: usize },
+    #[error("the slug can only contain lowercase letters, digits and dashes.")]
+    InvalidCharacter,
+    #[error("the slug cannot start or end with a dash.")]
+    LeadingOrTrailingDash
+}
+
+impl ListSlug {
+    const MAX_LENGTH: usize = 64;
+
+    pub fn parse(s: String) -> Result<ListSlug, ListSlugError> {
+        if s.is_empty() {
+            return Err(ListSlugError::Empty);
+        }
+        if s.len() > Self::MAX_LENGTH {
+            return Err(ListSlugError::TooLong { max_length: Self::MAX_LENGTH });
+        }
+        if !s.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+            return Err(ListSlugError::InvalidCharacter);
+        }
+        if s.starts_with('-') || s.ends_with('-') {
+            return Err(ListSlugError::LeadingOrTrailingDash);
+        }
+
+        Ok(ListSlug(s))
+    }
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{ListSlug, ListSlugError};
+
+    #[test]
+    fn a_valid_slug_is_parsed_successfully() {
+        assert!(ListSlug::parse("weekly-digest-2".into()).is_ok());
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        assert_eq!(ListSlug::parse("".into()).err(), Some(ListSlugError::Empty));
+    }
+
+    #[test]
+    fn a_slug_longer_than_64_characters_is_rejected() {
+        assert_eq!(
+            ListSlug::parse("a".repeat(65)).err(),
+            Some(ListSlugError::TooLong { max_length: 64 })
+        );
+    }
+
+    #[test]
+    fn slugs_with_invalid_characters_are_rejected() {
+        for slug in ["Weekly", "weekly digest", "weekly_digest", "café", "a/b"] {
+            assert_eq!(
+                ListSlug::parse(slug.into()).err(),
+                Some(ListSlugError::InvalidCharacter),
+                "{}",
+                slug
+            );
+        }
+    }
+
+    #[test]
+    fn slugs_starting_or_ending_with_a_dash_are_rejected() {
+        for slug in ["-weekly", "weekly-"] {
+            assert_eq!(
+                ListSlug::parse(slug.into()).err(),
+                Some(ListSlugError::LeadingOrTrailingDash)
+            );
+        }
+    }
+}
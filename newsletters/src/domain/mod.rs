@@ -1,7 +1,15 @@
 mod subscriber_name;
 mod subscriber_email;
 mod new_subscriber;
+mod unsubscribe_token;
+mod form_token;
+mod list_slug;
+mod list_name;
 
-pub use subscriber_name::SubscriberName;
-pub use subscriber_email::SubscriberEmail;
-pub use new_subscriber::NewSubscriber;
\ No newline at end of file
+pub use subscriber_name::{SubscriberName, SubscriberNameError};
+pub use subscriber_email::{SubscriberEmail, SubscriberEmailError};
+pub use new_subscriber::{NewSubscriber, NewSubscriberError};
+pub use unsubscribe_token::UnsubscribeToken;
+pub use form_token::FormToken;
+pub use list_slug::{ListSlug, ListSlugError};
+pub use list_name::{ListName, ListNameError};
\ No newline at end of file
@@ -1,9 +1,53 @@
-use crate::domain::subscriber_name::SubscriberName;
+use crate::domain::subscriber_name::{SubscriberName, SubscriberNameError};
 
-use super::SubscriberEmail;
+use super::{SubscriberEmail, SubscriberEmailError};
 
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName
 }
+
+/*
+    every field is validated, even if an earlier one failed,
+    so that the caller can report all the problems at once
+ */
+#[derive(thiserror::Error, Debug)]
+#[error("the subscriber details are invalid.")]
+pub struct NewSubscriberError {
+    pub name: Option<SubscriberNameError>,
+    pub email: Option<SubscriberEmailError>
+}
+
+impl NewSubscriber {
+    pub fn parse(name: String, email: String) -> Result<NewSubscriber, NewSubscriberError> {
+        match (SubscriberName::parse(name), SubscriberEmail::parse(email)) {
+            (Ok(name), Ok(email)) => Ok(NewSubscriber { email, name }),
+            (name, email) => Err(NewSubscriberError {
+                name: name.err(),
+                email: email.err()
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{NewSubscriber, SubscriberEmailError, SubscriberNameError};
+
+    #[test]
+    fn errors_are_reported_for_every_invalid_field() {
+        let error = NewSubscriber::parse("".into(), "".into()).err().unwrap();
+
+        assert_eq!(error.name, Some(SubscriberNameError::Empty));
+        assert_eq!(error.email, Some(SubscriberEmailError::Empty));
+    }
+
+    #[test]
+    fn valid_fields_are_not_reported() {
+        let error = NewSubscriber::parse("Ursula".into(), "ursula".into()).err().unwrap();
+
+        assert_eq!(error.name, None);
+        assert!(matches!(error.email, Some(SubscriberEmailError::InvalidSyntax(_))));
+    }
+}
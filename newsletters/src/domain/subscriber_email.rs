@@ -1,28 +1,209 @@
 
+use unicode_normalization::UnicodeNormalization;
 use validator::validate_email;
 
+// providers most of our subscribers use, and most often misspell
+const WELL_KNOWN_DOMAINS: &[&str] = &[
+    "gmail.com",
+    "googlemail.com",
+    "yahoo.com",
+    "yahoo.fr",
+    "yahoo.co.uk",
+    "hotmail.com",
+    "hotmail.fr",
+    "hotmail.co.uk",
+    "outlook.com",
+    "live.com",
+    "msn.com",
+    "icloud.com",
+    "me.com",
+    "aol.com",
+    "protonmail.com",
+    "proton.me",
+    "gmx.com",
+    "gmx.de",
+    "web.de",
+    "mail.com",
+    "yandex.ru",
+    "zoho.com",
+    "comcast.net",
+    "orange.fr",
+    "free.fr",
+    "t-online.de"
+];
+
+// real mail domains a letter or two away from a well-known one,
+// never taken for a typo: `email.com` is not a misspelled `gmail.com`
+const LOOKALIKE_DOMAINS: &[&str] = &[
+    "email.com",
+    "gmx.at",
+    "gmx.ch",
+    "gmx.fr",
+    "gmx.net",
+    "ymail.com",
+    "mail.de",
+    "mail.ru",
+    "mac.com",
+    "aim.com",
+    "hey.com",
+    "pm.me",
+    "live.fr",
+    "live.de",
+    "live.nl",
+    "yahoo.de",
+    "yahoo.es",
+    "yahoo.it",
+    "hotmail.de",
+    "hotmail.es",
+    "hotmail.it",
+    "outlook.fr",
+    "outlook.de",
+    "web.com",
+    "msn.fr",
+    "att.net",
+    "cox.net",
+    "bk.ru",
+    "ya.ru",
+    "sfr.fr",
+    "qq.com",
+    "126.com",
+    "163.com"
+];
+
+/*
+    a valid email address, normalized: trimmed, in Unicode NFC, with
+    its domain lowercased and converted to punycode
+
+    the local part keeps the case the subscriber typed, it is what we
+    display and send to; the canonical form lowercases it as well and
+    is what tells two subscribers apart, since no mail provider we know
+    of treats `Alice@` and `alice@` as different mailboxes
+ */
 #[derive(Debug)]
-pub struct SubscriberEmail(String);
+pub struct SubscriberEmail {
+    address: String,
+    canonical: String
+}
+
+/// Why a subscriber email was rejected.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SubscriberEmailError {
+    #[error("the email cannot be empty.")]
+    Empty,
+    #[error("{0} is not a valid email address.")]
+    InvalidSyntax(String),
+    #[error("addresses at {0} cannot subscribe.")]
+    DomainNotAllowed(String),
+    #[error("{0} is a disposable email domain, use a permanent address.")]
+    DisposableDomain(String),
+    // carries the address we suggest instead
+    #[error("did you mean {0}?")]
+    PossibleTypo(String)
+}
 
 impl SubscriberEmail {
-    pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validate_email(&s) {
-            Ok(Self(s))
-        } else {
-            Err(format!("{} is not a valid subscriber email.", s))
+    pub fn parse(s: String) -> Result<SubscriberEmail, SubscriberEmailError> {
+        if s.trim().is_empty() {
+            return Err(SubscriberEmailError::Empty);
+        }
+
+        let address = s.trim().nfc().collect::<String>();
+        let address = match address.rsplit_once('@') {
+            Some((local_part, domain)) => match idna::domain_to_ascii(domain) {
+                Ok(domain) => format!("{}@{}", local_part, domain),
+                Err(_) => return Err(SubscriberEmailError::InvalidSyntax(s))
+            },
+            None => return Err(SubscriberEmailError::InvalidSyntax(s))
+        };
+        if !validate_email(&address) {
+            return Err(SubscriberEmailError::InvalidSyntax(s));
         }
+
+        let canonical = address.to_lowercase();
+        Ok(Self { address, canonical })
+    }
+
+    /// The form two addresses are compared on.
+    pub fn canonical(&self) -> &str {
+        &self.canonical
+    }
+
+    /// The domain, lowercase and in punycode.
+    pub fn domain(&self) -> &str {
+        self.canonical.rsplit_once('@').map(|(_, domain)| domain).unwrap_or_default()
+    }
+
+    /*
+        the same address at the well-known domain its own domain is
+        most likely a typo of, if any
+
+        short domains only get one edit of slack: two edits away from
+        `me.com` is about any domain of six characters; the lookalikes
+        we know to be real are left alone
+     */
+    pub fn suggestion(&self) -> Option<String> {
+        let domain = self.domain();
+        if WELL_KNOWN_DOMAINS.contains(&domain) || LOOKALIKE_DOMAINS.contains(&domain) {
+            return None;
+        }
+        let (local_part, _) = self.address.rsplit_once('@')?;
+
+        WELL_KNOWN_DOMAINS
+            .iter()
+            .map(|known| (edit_distance(domain, known), known))
+            .filter(|(distance, known)| *distance <= if known.len() < 10 { 1 } else { 2 })
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, known)| format!("{}@{}", local_part, known))
     }
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.address
     }
 }
 
+impl std::fmt::Display for SubscriberEmail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.address.fmt(f)
+    }
+}
+
+/*
+    number of insertions, deletions, substitutions and transpositions
+    of adjacent characters turning `a` into `b`, the optimal string
+    alignment flavour of Damerau-Levenshtein: `gmial` is one edit away
+    from `gmail`, not two
+ */
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances[i][j] between the first i characters of a and the first j of b
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
 #[cfg(test)]
 mod tests {
-    use super::SubscriberEmail;
+    use super::{edit_distance, SubscriberEmail, SubscriberEmailError};
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
     // use quickcheck::Gen;
@@ -31,12 +212,15 @@ mod tests {
     #[test]
     fn empty_string_is_rejected() {
         let email = "".to_string();
-        assert!(SubscriberEmail::parse(email).is_err());
+        assert_eq!(SubscriberEmail::parse(email).err(), Some(SubscriberEmailError::Empty));
     }
     #[test]
     fn email_missing_at_symbol_is_rejected() {
         let email = "ursuladomain.com".to_string();
-        assert!(SubscriberEmail::parse(email).is_err());
+        assert_eq!(
+            SubscriberEmail::parse(email.clone()).err(),
+            Some(SubscriberEmailError::InvalidSyntax(email))
+        );
     }
     #[test]
     fn email_missing_subject_is_rejected() {
@@ -44,6 +228,113 @@ mod tests {
         assert!(SubscriberEmail::parse(email).is_err());
     }
 
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email = SubscriberEmail::parse("  ursula@domain.com\n".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "ursula@domain.com");
+    }
+
+    #[test]
+    fn the_domain_is_lowercased_but_not_the_local_part() {
+        let email = SubscriberEmail::parse("Ursula.Le.Guin@Example.COM".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "Ursula.Le.Guin@example.com");
+        assert_eq!(email.canonical(), "ursula.le.guin@example.com");
+    }
+
+    #[test]
+    fn internationalized_domains_are_converted_to_punycode() {
+        let email = SubscriberEmail::parse("ursula@Bücher.example".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn unicode_is_normalized_to_nfc() {
+        // "u" followed by a combining diaeresis, "ü" once composed
+        let decomposed = SubscriberEmail::parse("ursula@bu\u{308}cher.example".to_string()).unwrap();
+        let composed = SubscriberEmail::parse("ursula@b\u{fc}cher.example".to_string()).unwrap();
+
+        assert_eq!(decomposed.canonical(), composed.canonical());
+    }
+
+    #[test]
+    fn addresses_differing_only_by_case_share_a_canonical_form() {
+        let first = SubscriberEmail::parse("Alice@Example.COM".to_string()).unwrap();
+        let second = SubscriberEmail::parse("alice@example.com".to_string()).unwrap();
+
+        assert_eq!(first.canonical(), second.canonical());
+    }
+
+    #[test]
+    fn invalid_domains_are_rejected() {
+        let email = "ursula@exa mple.com".to_string();
+        assert_eq!(
+            SubscriberEmail::parse(email.clone()).err(),
+            Some(SubscriberEmailError::InvalidSyntax(email))
+        );
+    }
+
+    #[test]
+    fn edit_distances_count_transpositions_as_one_edit() {
+        assert_eq!(edit_distance("gmail.com", "gmail.com"), 0);
+        assert_eq!(edit_distance("gmial.com", "gmail.com"), 1);
+        assert_eq!(edit_distance("outlok.com", "outlook.com"), 1);
+        assert_eq!(edit_distance("hotmial.co", "hotmail.com"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn typos_of_well_known_domains_get_a_suggestion() {
+        let cases = [
+            ("ursula@gmial.com", "ursula@gmail.com"),
+            ("ursula@outlok.com", "ursula@outlook.com"),
+            ("Ursula@Hotmial.co", "Ursula@hotmail.com"),
+            ("ursula@yahoo.con", "ursula@yahoo.com")
+        ];
+
+        for (typed, suggested) in cases {
+            let email = SubscriberEmail::parse(typed.to_string()).unwrap();
+            assert_eq!(email.suggestion().as_deref(), Some(suggested), "{}", typed);
+        }
+    }
+
+    #[test]
+    fn well_known_and_unrelated_domains_get_no_suggestion() {
+        for typed in ["ursula@gmail.com", "ursula@mail.com", "ursula@example.com", "ursula@example.org"] {
+            let email = SubscriberEmail::parse(typed.to_string()).unwrap();
+            assert_eq!(email.suggestion(), None, "{}", typed);
+        }
+    }
+
+    #[test]
+    fn real_domains_close_to_well_known_ones_get_no_suggestion() {
+        let typed = [
+            "ursula@email.com",
+            "ursula@gmx.net",
+            "ursula@ymail.com",
+            "ursula@mail.ru",
+            "ursula@mac.com",
+            "ursula@live.fr",
+            "ursula@yahoo.de",
+            "ursula@hotmail.de",
+            "ursula@aim.com"
+        ];
+
+        for typed in typed {
+            let email = SubscriberEmail::parse(typed.to_string()).unwrap();
+            assert_eq!(email.suggestion(), None, "{}", typed);
+        }
+    }
+
+    #[test]
+    fn lookalike_domains_are_not_well_known_ones() {
+        for domain in super::LOOKALIKE_DOMAINS {
+            assert!(!super::WELL_KNOWN_DOMAINS.contains(domain), "{}", domain);
+        }
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
 
@@ -2,15 +2,31 @@ use unicode_segmentation::UnicodeSegmentation;
 
 pub struct SubscriberName(String);
 
+/// Why a subscriber name was rejected.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SubscriberNameError {
+    #[error("the name cannot be empty.")]
+    Empty,
+    #[error("the name cannot be longer than {max_length} characters.")]
+    TooLong { max_length: usize },
+    #[error("the name cannot contain '{0}'.")]
+    ForbiddenCharacter(char)
+}
+
 impl SubscriberName {
+    const MAX_LENGTH: usize = 256;
+    const FORBIDDEN_CHARACTERS: [char; 9] = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+
     /// Returns an instance of SubscriberName if the input satisfies all
     /// out validation constrains on subscriber names.
-    /// It panics otherwise
-    pub fn parse(s: String) -> Result<SubscriberName, String> {
+    /// It returns the first violated constraint otherwise.
+    pub fn parse(s: String) -> Result<SubscriberName, SubscriberNameError> {
         // .trim() returns a view over the input s without trailing
         // whitespace-like characters.
         // .is_empty checks if the view contains any character.
-        let is_empty_or_whitespace = s.trim().is_empty();
+        if s.trim().is_empty() {
+            return Err(SubscriberNameError::Empty);
+        }
 
         // A grapheme is defined by the Unicode standard as a "user-perceived"
         // character: `å` is a single grapheme, but it is composed of two characters
@@ -19,18 +35,17 @@ impl SubscriberName {
         // `graphemes` returns an iterator over the graphemes in the input `s`.
         // `true` specifies that we want to use the extended grapheme definition set,
         // the recommended one.
-        let is_too_long = s.graphemes(true).count() > 256;
-
-        // Iterate over all characters in the input `s` to check if any of them matches
-        // one of the characters in the forbidden array.
-        let forbidden_characters = ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
-        let contains_forbidden_characters = s.chars().any(|g| forbidden_characters.contains(&g));
+        if s.graphemes(true).count() > Self::MAX_LENGTH {
+            return Err(SubscriberNameError::TooLong { max_length: Self::MAX_LENGTH });
+        }
 
-        if is_empty_or_whitespace || is_too_long || contains_forbidden_characters {
-            Err(format!("{} is not a valid subscriber name.", s))
-        } else {
-            Ok(SubscriberName(s))
+        // Iterate over all characters in the input `s` to find the first one
+        // matching one of the characters in the forbidden array.
+        if let Some(c) = s.chars().find(|c| Self::FORBIDDEN_CHARACTERS.contains(c)) {
+            return Err(SubscriberNameError::ForbiddenCharacter(c));
         }
+
+        Ok(SubscriberName(s))
     }
 }
 
@@ -44,7 +59,7 @@ impl AsRef<str> for SubscriberName {
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::SubscriberName;
+    use crate::domain::{SubscriberName, SubscriberNameError};
 
     #[test]
     fn a_256_grapheme_long_name_is_valid() {
@@ -55,25 +70,31 @@ mod tests {
     #[test]
     fn a_name_longer_than_256_graphemes_is_rejected() {
         let name = "a".repeat(257);
-        assert!(SubscriberName::parse(name).is_err());
+        assert_eq!(
+            SubscriberName::parse(name).err(),
+            Some(SubscriberNameError::TooLong { max_length: 256 })
+        );
     }
 
     #[test]
     fn whitespace_only_names_are_rejected() {
         let name = " ".to_string();
-        assert!(SubscriberName::parse(name).is_err());
+        assert_eq!(SubscriberName::parse(name).err(), Some(SubscriberNameError::Empty));
     }
 
     #[test]
     fn empty_string_is_rejected() {
         let name = "".to_string();
-        assert!(SubscriberName::parse(name).is_err());
+        assert_eq!(SubscriberName::parse(name).err(), Some(SubscriberNameError::Empty));
     }
     #[test]
     fn names_containing_an_invalid_character_are_rejected() {
-        for name in &['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
-            let name = name.to_string();
-            assert!(SubscriberName::parse(name).is_err());
+        for c in &['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
+            let name = format!("Ursula {} Le Guin", c);
+            assert_eq!(
+                SubscriberName::parse(name).err(),
+                Some(SubscriberNameError::ForbiddenCharacter(*c))
+            );
         }
     }
     #[test]
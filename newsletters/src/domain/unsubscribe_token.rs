@@ -0,0 +1,184 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/*
+    token carried by the unsubscribe links: the subscriber and list ids
+    followed by an HMAC of those ids, so that nobody can forge a link
+    for somebody else
+
+    nothing is stored, the signature is checked against our secret
+ */
+#[derive(Debug)]
+pub struct UnsubscribeToken {
+    subscriber_id: Uuid,
+    // `None` for links sent before we had several lists,
+    // they were all about the default one
+    list_id: Option<Uuid>,
+    token: String
+}
+
+impl UnsubscribeToken {
+    pub fn generate(subscriber_id: Uuid, list_id: Uuid, hmac_secret: &Secret<String>) -> Self {
+        let signature = URL_SAFE_NO_PAD.encode(signature(subscriber_id, Some(list_id), hmac_secret));
+        let token = format!("{}.{}.{}", subscriber_id.simple(), list_id.simple(), signature);
+        Self {
+            subscriber_id,
+            list_id: Some(list_id),
+            token
+        }
+    }
+
+    pub fn parse(s: String, hmac_secret: &Secret<String>) -> Result<UnsubscribeToken, String> {
+        let malformed = || "the unsubscribe token is malformed.".to_string();
+        let parts: Vec<&str> = s.split('.').collect();
+        let (subscriber_id, list_id, signature) = match parts[..] {
+            [subscriber_id, signature] => (subscriber_id, None, signature),
+            [subscriber_id, list_id, signature] => (subscriber_id, Some(list_id), signature),
+            _ => return Err(malformed())
+        };
+        let subscriber_id = Uuid::try_parse(subscriber_id).map_err(|_| malformed())?;
+        let list_id = list_id
+            .map(Uuid::try_parse)
+            .transpose()
+            .map_err(|_| malformed())?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| malformed())?;
+
+        // constant-time comparison, we do not want to leak
+        // how many bytes of a forged signature are correct
+        mac(subscriber_id, list_id, hmac_secret)
+            .verify_slice(&signature)
+            .map_err(|_| "the unsubscribe token signature is invalid.".to_string())?;
+
+        Ok(Self { subscriber_id, list_id, token: s })
+    }
+
+    pub fn subscriber_id(&self) -> Uuid {
+        self.subscriber_id
+    }
+
+    pub fn list_id(&self) -> Option<Uuid> {
+        self.list_id
+    }
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.token
+    }
+}
+
+fn mac(subscriber_id: Uuid, list_id: Option<Uuid>, hmac_secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size");
+    // the prefix keeps these signatures apart from anything else
+    // we may sign with the same secret
+    mac.update(b"unsubscribe:");
+    mac.update(subscriber_id.as_bytes());
+    if let Some(list_id) = list_id {
+        mac.update(list_id.as_bytes());
+    }
+    mac
+}
+
+fn signature(subscriber_id: Uuid, list_id: Option<Uuid>, hmac_secret: &Secret<String>) -> Vec<u8> {
+    mac(subscriber_id, list_id, hmac_secret).finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{signature, UnsubscribeToken};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-long-and-secret-random-key-needed-to-verify-message-integrity".into())
+    }
+
+    #[test]
+    fn a_generated_token_is_accepted() {
+        let subscriber_id = Uuid::new_v4();
+        let list_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, list_id, &secret());
+
+        let parsed = UnsubscribeToken::parse(token.as_ref().to_owned(), &secret()).unwrap();
+
+        assert_eq!(parsed.subscriber_id(), subscriber_id);
+        assert_eq!(parsed.list_id(), Some(list_id));
+    }
+
+    #[test]
+    fn a_token_issued_before_lists_existed_is_accepted() {
+        let subscriber_id = Uuid::new_v4();
+        let signature = URL_SAFE_NO_PAD.encode(signature(subscriber_id, None, &secret()));
+        let token = format!("{}.{}", subscriber_id.simple(), signature);
+
+        let parsed = UnsubscribeToken::parse(token, &secret()).unwrap();
+
+        assert_eq!(parsed.subscriber_id(), subscriber_id);
+        assert_eq!(parsed.list_id(), None);
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = UnsubscribeToken::generate(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            &Secret::new("another-secret".into())
+        );
+
+        assert!(UnsubscribeToken::parse(token.as_ref().to_owned(), &secret()).is_err());
+    }
+
+    #[test]
+    fn a_token_for_another_subscriber_is_rejected() {
+        let token = UnsubscribeToken::generate(Uuid::new_v4(), Uuid::new_v4(), &secret());
+        let (_, rest) = token.as_ref().split_once('.').unwrap();
+        let forged = format!("{}.{}", Uuid::new_v4().simple(), rest);
+
+        assert!(UnsubscribeToken::parse(forged, &secret()).is_err());
+    }
+
+    #[test]
+    fn a_token_for_another_list_is_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, Uuid::new_v4(), &secret());
+        let signature = token.as_ref().rsplit_once('.').unwrap().1;
+        let forged = format!("{}.{}.{}", subscriber_id.simple(), Uuid::new_v4().simple(), signature);
+
+        assert!(UnsubscribeToken::parse(forged, &secret()).is_err());
+    }
+
+    #[test]
+    fn a_token_with_its_list_stripped_is_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::generate(subscriber_id, Uuid::new_v4(), &secret());
+        let signature = token.as_ref().rsplit_once('.').unwrap().1;
+        let forged = format!("{}.{}", subscriber_id.simple(), signature);
+
+        assert!(UnsubscribeToken::parse(forged, &secret()).is_err());
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let subscriber_id = Uuid::new_v4().simple().to_string();
+        let malformed = [
+            "".to_string(),
+            subscriber_id.clone(),
+            format!("{}.", subscriber_id),
+            format!("{}.not-base64!", subscriber_id),
+            "not-a-uuid.c2lnbmF0dXJl".to_string(),
+            format!("{}.not-a-uuid.c2lnbmF0dXJl", subscriber_id),
+            format!("{0}.{0}.{0}.c2lnbmF0dXJl", subscriber_id)
+        ];
+
+        for token in malformed {
+            assert!(UnsubscribeToken::parse(token.clone(), &secret()).is_err(), "{}", token);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::domain::{SubscriberEmail, SubscriberEmailError};
+
+// shipped with the binary, used unless another file is configured
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("../configuration/disposable_domains.txt");
+
+/// How the configured domains are applied.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainPolicyMode {
+    // only the listed domains can subscribe, for internal newsletters
+    Allowlist,
+    // any domain but the listed ones can subscribe
+    #[default]
+    Denylist
+}
+
+/// Where the domains of a policy come from.
+#[derive(Debug, Clone)]
+pub struct DomainSources {
+    pub mode: DomainPolicyMode,
+    pub domains: Vec<String>,
+    // one domain per line, added to `domains`
+    pub domains_path: Option<PathBuf>,
+    pub block_disposable: bool,
+    // replaces the bundled list of disposable domains
+    pub disposable_domains_path: Option<PathBuf>
+}
+
+struct Rules {
+    listed: HashSet<String>,
+    disposable: HashSet<String>
+}
+
+// what tells us that a file changed since we read it
+type FileVersion = Option<(SystemTime, u64)>;
+
+struct PolicyState {
+    rules: Arc<Rules>,
+    versions: Vec<FileVersion>,
+    checked_at: Instant
+}
+
+/*
+    which email domains are allowed to subscribe
+
+    the files are watched for changes: they are checked at most once per
+    `reload_interval`, when a signup comes in, and read again if they were
+    modified; a file that cannot be read or parsed anymore is reported and
+    the rules loaded before are kept
+
+    the files are read on the blocking thread pool, the lock is only held
+    to swap the rules: concurrent signups keep using the previous ones
+ */
+pub struct DomainPolicy {
+    sources: Arc<DomainSources>,
+    reload_interval: Duration,
+    state: Mutex<PolicyState>
+}
+
+impl DomainPolicy {
+    pub fn new(sources: DomainSources, reload_interval: Duration) -> Result<Self, String> {
+        let versions = file_versions(&sources);
+        let rules = load_rules(&sources)?;
+
+        Ok(Self {
+            sources: Arc::new(sources),
+            reload_interval,
+            state: Mutex::new(PolicyState {
+                rules: Arc::new(rules),
+                versions,
+                checked_at: Instant::now()
+            })
+        })
+    }
+
+    pub async fn check(&self, email: &SubscriberEmail) -> Result<(), SubscriberEmailError> {
+        let rules = self.current_rules().await;
+        let domain = email.domain();
+
+        let listed = matches_any(domain, &rules.listed);
+        match self.sources.mode {
+            DomainPolicyMode::Allowlist if !listed => {
+                return Err(SubscriberEmailError::DomainNotAllowed(domain.to_owned()))
+            }
+            DomainPolicyMode::Denylist if listed => {
+                return Err(SubscriberEmailError::DomainNotAllowed(domain.to_owned()))
+            }
+            _ => {}
+        }
+        if self.sources.block_disposable && matches_any(domain, &rules.disposable) {
+            return Err(SubscriberEmailError::DisposableDomain(domain.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    async fn current_rules(&self) -> Arc<Rules> {
+        let loaded_versions = {
+            let mut state = self.state.lock().unwrap();
+            if state.checked_at.elapsed() < self.reload_interval {
+                return state.rules.clone();
+            }
+            // the other signups carry on with the current rules meanwhile
+            state.checked_at = Instant::now();
+            state.versions.clone()
+        };
+
+        let sources = self.sources.clone();
+        let reloaded = tokio::task::spawn_blocking(move || {
+            let versions = file_versions(&sources);
+            if versions == loaded_versions {
+                return None;
+            }
+            Some(load_rules(&sources).map(|rules| (rules, versions)))
+        })
+        .await
+        .unwrap_or_else(|e| Some(Err(format!("the reload task failed: {}", e))));
+
+        let mut state = self.state.lock().unwrap();
+        match reloaded {
+            Some(Ok((rules, versions))) => {
+                tracing::info!("the domain policy was reloaded");
+                state.rules = Arc::new(rules);
+                state.versions = versions;
+            }
+            Some(Err(e)) => tracing::error!(
+                error.message = %e,
+                "failed to reload the domain policy, the previous rules are kept"
+            ),
+            None => {}
+        }
+        state.rules.clone()
+    }
+}
+
+fn file_versions(sources: &DomainSources) -> Vec<FileVersion> {
+    [&sources.domains_path, &sources.disposable_domains_path]
+        .into_iter()
+        .map(|path| {
+            let metadata = std::fs::metadata(path.as_ref()?).ok()?;
+            Some((metadata.modified().ok()?, metadata.len()))
+        })
+        .collect()
+}
+
+fn load_rules(sources: &DomainSources) -> Result<Rules, String> {
+    let mut listed = parse_domains(&sources.domains.join("\n"))
+        .map_err(|e| format!("invalid domain policy: {}", e))?;
+    if let Some(path) = &sources.domains_path {
+        listed.extend(read_domains(path)?);
+    }
+    let disposable = match &sources.disposable_domains_path {
+        Some(path) => read_domains(path)?,
+        None => parse_domains(BUNDLED_DISPOSABLE_DOMAINS).expect("the bundled disposable domains are valid")
+    };
+
+    Ok(Rules { listed, disposable })
+}
+
+fn read_domains(path: &PathBuf) -> Result<HashSet<String>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read the domain list {}: {}", path.display(), e))?;
+    parse_domains(&content).map_err(|e| format!("invalid domain list {}: {}", path.display(), e))
+}
+
+/*
+    one domain per line, blank lines and `#` comments are ignored
+
+    domains are compared in the form `SubscriberEmail` gives them:
+    lowercase, internationalized ones in punycode
+ */
+fn parse_domains(content: &str) -> Result<HashSet<String>, String> {
+    let mut domains = HashSet::new();
+    for (i, line) in content.lines().enumerate() {
+        let domain = line.split('#').next().unwrap_or_default().trim();
+        if domain.is_empty() {
+            continue;
+        }
+        let domain = idna::domain_to_ascii(domain)
+            .ok()
+            .filter(|d| !d.is_empty() && d.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+            .ok_or_else(|| format!("{} on line {} is not a domain", domain, i + 1))?;
+        domains.insert(domain);
+    }
+    Ok(domains)
+}
+
+// a listed domain covers its subdomains as well
+fn matches_any(domain: &str, domains: &HashSet<String>) -> bool {
+    let mut candidate = domain;
+    loop {
+        if domains.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DomainPolicy, DomainPolicyMode, DomainSources};
+    use crate::domain::{SubscriberEmail, SubscriberEmailError};
+    use std::time::Duration;
+
+    fn policy(mode: DomainPolicyMode, domains: &[&str]) -> DomainPolicy {
+        let sources = DomainSources {
+            mode,
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+            domains_path: None,
+            block_disposable: true,
+            disposable_domains_path: None
+        };
+        DomainPolicy::new(sources, Duration::from_secs(60)).unwrap()
+    }
+
+    async fn check(policy: &DomainPolicy, email: &str) -> Result<(), SubscriberEmailError> {
+        policy.check(&SubscriberEmail::parse(email.to_string()).unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn denied_domains_and_their_subdomains_are_rejected() {
+        let policy = policy(DomainPolicyMode::Denylist, &["spam.example"]);
+
+        assert!(check(&policy, "ursula@example.com").await.is_ok());
+        assert_eq!(
+            check(&policy, "ursula@Spam.Example").await,
+            Err(SubscriberEmailError::DomainNotAllowed("spam.example".into()))
+        );
+        assert!(check(&policy, "ursula@eu.spam.example").await.is_err());
+        assert!(check(&policy, "ursula@notspam.example").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn only_allowed_domains_are_accepted_in_allowlist_mode() {
+        let policy = policy(DomainPolicyMode::Allowlist, &["corp.example"]);
+
+        assert!(check(&policy, "ursula@corp.example").await.is_ok());
+        assert!(check(&policy, "ursula@eng.corp.example").await.is_ok());
+        assert!(check(&policy, "ursula@example.com").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn disposable_domains_are_rejected_with_their_own_error() {
+        let policy = policy(DomainPolicyMode::Denylist, &[]);
+
+        assert_eq!(
+            check(&policy, "ursula@mailinator.com").await,
+            Err(SubscriberEmailError::DisposableDomain("mailinator.com".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn internationalized_domains_are_listed_in_any_form() {
+        let policy = policy(DomainPolicyMode::Denylist, &["bücher.example"]);
+
+        assert!(check(&policy, "ursula@xn--bcher-kva.example").await.is_err());
+    }
+
+    #[test]
+    fn invalid_domains_are_reported() {
+        let sources = DomainSources {
+            mode: DomainPolicyMode::Denylist,
+            domains: vec!["spam .example".into()],
+            domains_path: None,
+            block_disposable: false,
+            disposable_domains_path: None
+        };
+
+        assert!(DomainPolicy::new(sources, Duration::from_secs(60)).is_err());
+    }
+}
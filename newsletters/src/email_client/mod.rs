@@ -0,0 +1,103 @@
+mod http;
+mod smtp;
+
+pub use http::HttpTransport;
+pub use smtp::{SmtpTls, SmtpTransport};
+
+use crate::domain::SubscriberEmail;
+use crate::metrics::metrics;
+
+/*
+    client to send outbound emails
+
+    the actual delivery is delegated to one of the supported
+    transports, selected in the `email_client` configuration section
+ */
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: EmailTransport
+}
+
+pub enum EmailTransport {
+    // REST API of a Postmark-style email delivery provider
+    Http(HttpTransport),
+    // relay through an SMTP server
+    Smtp(SmtpTransport)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    #[error("failed to call the email API")]
+    Http(#[from] reqwest::Error),
+    #[error("failed to build the email message")]
+    Message(#[from] lettre::error::Error),
+    #[error("invalid email address")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("failed to deliver the email through SMTP")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("the SMTP server did not answer")]
+    SmtpUnresponsive
+}
+
+/// Additional header attached to an outgoing email, e.g. `List-Unsubscribe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailHeader {
+    pub name: &'static str,
+    pub value: String
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: EmailTransport) -> Self {
+        Self { sender, transport }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str
+    ) -> Result<(), SendEmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /*
+        whether the transport can be reached, without sending anything
+
+        used by the readiness probe: an email provider that is down
+        means confirmation emails cannot go out
+     */
+    pub async fn check_connection(&self) -> Result<(), SendEmailError> {
+        match &self.transport {
+            EmailTransport::Http(transport) => transport.check_connection().await,
+            EmailTransport::Smtp(transport) => transport.check_connection().await
+        }
+    }
+
+    pub async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader]
+    ) -> Result<(), SendEmailError> {
+        let (transport_name, outcome) = match &self.transport {
+            EmailTransport::Http(transport) => {
+                let outcome = transport
+                    .send_email(&self.sender, recipient, subject, html_content, text_content, headers)
+                    .await;
+                ("http", outcome)
+            }
+            EmailTransport::Smtp(transport) => {
+                let outcome = transport
+                    .send_email(&self.sender, recipient, subject, html_content, text_content, headers)
+                    .await;
+                ("smtp", outcome)
+            }
+        };
+        metrics().record_email_sent(transport_name, outcome.is_ok());
+        outcome
+    }
+}
@@ -0,0 +1,360 @@
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::domain::SubscriberEmail;
+use super::{EmailHeader, SendEmailError};
+
+/// How the connection to the SMTP server is secured.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain connection upgraded with `STARTTLS`, failing if the server
+    /// does not support it.
+    Starttls,
+    /// TLS from the first byte (SMTPS, usually on port 465).
+    Tls,
+    /// No encryption at all, only meant for local SMTP sinks.
+    None
+}
+
+/*
+    transport relaying emails through an SMTP server
+ */
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>
+}
+
+impl SmtpTransport {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        // no AUTH at all when missing
+        credentials: Option<(String, Secret<String>)>,
+        timeout: std::time::Duration
+    ) -> Result<Self, SendEmailError> {
+        let builder = match tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+
+        let mut builder = builder.port(port).timeout(Some(timeout));
+        // AUTH is issued once the connection is secured
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password.expose_secret().to_owned()));
+        }
+        let mailer = builder.build();
+
+        Ok(Self { mailer })
+    }
+
+    // opens a connection, or takes one from the pool, and sends a NOOP
+    pub async fn check_connection(&self) -> Result<(), SendEmailError> {
+        if !self.mailer.test_connection().await? {
+            return Err(SendEmailError::SmtpUnresponsive);
+        }
+        Ok(())
+    }
+
+    pub async fn send_email(
+        &self,
+        sender: &SubscriberEmail,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader]
+    ) -> Result<(), SendEmailError> {
+        let from: Mailbox = sender.as_ref().parse()?;
+        let to: Mailbox = recipient.as_ref().parse()?;
+
+        let mut builder = Message::builder()
+            .from(from)
+            .to(to)
+            .subject(subject);
+        for header in headers {
+            builder = builder.raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str(header.name),
+                header.value.clone()
+            ));
+        }
+
+        // multipart/alternative lets the email client pick
+        // the richest representation it is able to display
+        let message = builder
+            .multipart(MultiPart::alternative_plain_html(
+                text_content.to_owned(),
+                html_content.to_owned()
+            ))?;
+
+        self.mailer.send(message).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, EmailHeader, EmailTransport, SmtpTls, SmtpTransport};
+    use secrecy::Secret;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// A mail received by the [`SmtpSink`].
+    #[derive(Debug, Clone, Default)]
+    struct ReceivedMail {
+        auth: Option<String>,
+        mail_from: String,
+        rcpt_to: Vec<String>,
+        data: String
+    }
+
+    /// Minimal SMTP server accepting every message it is handed,
+    /// so that we can inspect what the transport puts on the wire.
+    struct SmtpSink {
+        port: u16,
+        received: Arc<Mutex<Vec<ReceivedMail>>>,
+        // every command line, including those of aborted sessions
+        commands: Arc<Mutex<Vec<String>>>
+    }
+
+    impl SmtpSink {
+        async fn start(rejected_recipient: Option<&'static str>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let commands = Arc::new(Mutex::new(Vec::new()));
+
+            let (store, log) = (received.clone(), commands.clone());
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let (store, log) = (store.clone(), log.clone());
+                    tokio::spawn(Self::serve(stream, store, log, rejected_recipient));
+                }
+            });
+
+            Self { port, received, commands }
+        }
+
+        async fn serve(
+            stream: tokio::net::TcpStream,
+            store: Arc<Mutex<Vec<ReceivedMail>>>,
+            log: Arc<Mutex<Vec<String>>>,
+            rejected_recipient: Option<&'static str>
+        ) {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut mail = ReceivedMail::default();
+
+            writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let command = line.to_uppercase();
+                log.lock().unwrap().push(command.clone());
+                // STARTTLS is never advertised
+                let reply: &[u8] = if command.starts_with("EHLO") {
+                    b"250-sink\r\n250 AUTH PLAIN LOGIN\r\n"
+                } else if command.starts_with("AUTH PLAIN") {
+                    mail.auth = Some(line[11..].to_string());
+                    b"235 2.7.0 Authentication successful\r\n"
+                } else if command.starts_with("MAIL FROM:") {
+                    mail.mail_from = line[10..].to_string();
+                    b"250 OK\r\n"
+                } else if command.starts_with("RCPT TO:") {
+                    let recipient = line[8..].to_string();
+                    if rejected_recipient.is_some_and(|r| recipient.contains(r)) {
+                        b"550 5.1.1 No such user\r\n"
+                    } else {
+                        mail.rcpt_to.push(recipient);
+                        b"250 OK\r\n"
+                    }
+                } else if command == "DATA" {
+                    writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await.unwrap();
+                    while let Ok(Some(data_line)) = lines.next_line().await {
+                        if data_line == "." {
+                            break;
+                        }
+                        mail.data.push_str(&data_line);
+                        mail.data.push('\n');
+                    }
+                    store.lock().unwrap().push(std::mem::take(&mut mail));
+                    b"250 OK queued\r\n"
+                } else if command == "QUIT" {
+                    writer.write_all(b"221 Bye\r\n").await.unwrap();
+                    return;
+                } else {
+                    // RSET, NOOP, ...
+                    b"250 OK\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+        }
+
+        fn received(&self) -> Vec<ReceivedMail> {
+            self.received.lock().unwrap().clone()
+        }
+
+        fn commands(&self) -> Vec<String> {
+            self.commands.lock().unwrap().clone()
+        }
+    }
+
+    fn email_client(port: u16) -> EmailClient {
+        email_client_with_credentials(port, Some(("newsletter".into(), Secret::new("password".into()))))
+    }
+
+    fn email_client_with_credentials(port: u16, credentials: Option<(String, Secret<String>)>) -> EmailClient {
+        email_client_with_tls(port, SmtpTls::None, credentials)
+    }
+
+    fn email_client_with_tls(
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, Secret<String>)>
+    ) -> EmailClient {
+        let transport = SmtpTransport::new(
+            "127.0.0.1",
+            port,
+            tls,
+            credentials,
+            std::time::Duration::from_secs(2)
+        )
+        .unwrap();
+        let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        EmailClient::new(sender, EmailTransport::Smtp(transport))
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_a_multipart_message_to_the_smtp_server() {
+        let sink = SmtpSink::start(None).await;
+        let email_client = email_client(sink.port);
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+
+        let outcome = email_client
+            .send_email(&recipient, "Issue #1", "<p>Hello!</p>", "Hello!")
+            .await;
+
+        assert!(outcome.is_ok(), "{:?}", outcome);
+        let received = sink.received();
+        assert_eq!(received.len(), 1);
+        let mail = &received[0];
+        assert_eq!(mail.mail_from, "<sender@example.com>");
+        assert_eq!(mail.rcpt_to, vec!["<ursula@example.com>".to_string()]);
+        assert!(mail.data.contains("Subject: Issue #1"));
+        assert!(mail.data.contains("Content-Type: multipart/alternative"));
+        assert!(mail.data.contains("Content-Type: text/plain"));
+        assert!(mail.data.contains("Content-Type: text/html"));
+        assert!(mail.data.contains("<p>Hello!</p>"));
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_adds_the_extra_headers_to_the_message() {
+        let sink = SmtpSink::start(None).await;
+        let email_client = email_client(sink.port);
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe-Post",
+            value: "List-Unsubscribe=One-Click".into()
+        }];
+
+        email_client
+            .send_email_with_headers(&recipient, "Issue #1", "<p>Hello!</p>", "Hello!", &headers)
+            .await
+            .unwrap();
+
+        let received = sink.received();
+        assert!(received[0].data.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+    }
+
+    #[tokio::test]
+    async fn send_email_authenticates_with_the_configured_credentials() {
+        let sink = SmtpSink::start(None).await;
+        let email_client = email_client(sink.port);
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+
+        email_client
+            .send_email(&recipient, "Issue #1", "<p>Hello!</p>", "Hello!")
+            .await
+            .unwrap();
+
+        // AUTH PLAIN carries base64("\0username\0password")
+        let received = sink.received();
+        assert_eq!(received[0].auth.as_deref(), Some("AG5ld3NsZXR0ZXIAcGFzc3dvcmQ="));
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_authenticate_without_credentials() {
+        let sink = SmtpSink::start(None).await;
+        let email_client = email_client_with_credentials(sink.port, None);
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+
+        email_client
+            .send_email(&recipient, "Issue #1", "<p>Hello!</p>", "Hello!")
+            .await
+            .unwrap();
+
+        let received = sink.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].auth, None);
+    }
+
+    #[tokio::test]
+    async fn starttls_fails_without_sending_credentials_if_the_server_does_not_offer_it() {
+        let sink = SmtpSink::start(None).await;
+        let email_client = email_client_with_tls(
+            sink.port,
+            SmtpTls::Starttls,
+            Some(("newsletter".into(), Secret::new("password".into())))
+        );
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+
+        let outcome = email_client
+            .send_email(&recipient, "Issue #1", "<p>Hello!</p>", "Hello!")
+            .await;
+
+        assert!(outcome.is_err());
+        let commands = sink.commands();
+        assert!(commands.iter().any(|c| c.starts_with("EHLO")), "{:?}", commands);
+        assert!(!commands.iter().any(|c| c.starts_with("AUTH")), "{:?}", commands);
+        assert!(sink.received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_smtp_server_rejects_the_recipient() {
+        let sink = SmtpSink::start(Some("ursula@example.com")).await;
+        let email_client = email_client(sink.port);
+        let recipient = SubscriberEmail::parse("ursula@example.com".into()).unwrap();
+
+        let outcome = email_client
+            .send_email(&recipient, "Issue #1", "<p>Hello!</p>", "Hello!")
+            .await;
+
+        assert!(outcome.is_err());
+        assert!(sink.received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn check_connection_succeeds_if_the_smtp_server_answers() {
+        let sink = SmtpSink::start(None).await;
+        let email_client = email_client(sink.port);
+
+        assert!(email_client.check_connection().await.is_ok());
+        assert!(sink.received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn check_connection_fails_if_nothing_listens_on_the_port() {
+        // bound then dropped, nobody is listening anymore
+        let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let email_client = email_client(port);
+
+        assert!(email_client.check_connection().await.is_err());
+    }
+}
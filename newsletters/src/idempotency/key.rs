@@ -0,0 +1,32 @@
+/// Client-provided key identifying a request that must not be processed twice.
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl TryFrom<String> for IdempotencyKey {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.is_empty() {
+            anyhow::bail!("the idempotency key cannot be empty");
+        }
+        // keys are stored in the database, we do not want
+        // clients to be able to fill it with huge values
+        let max_length = 50;
+        if s.len() >= max_length {
+            anyhow::bail!("the idempotency key must be shorter than {max_length} characters");
+        }
+        Ok(Self(s))
+    }
+}
+
+impl From<IdempotencyKey> for String {
+    fn from(k: IdempotencyKey) -> Self {
+        k.0
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
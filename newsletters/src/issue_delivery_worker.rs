@@ -0,0 +1,448 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::postgres::types::PgInterval;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::configuration::{Settings, WorkerSettings};
+use crate::confirmation_email_worker::try_send_confirmation_email;
+use crate::domain::{SubscriberEmail, UnsubscribeToken};
+use crate::email_client::{EmailClient, EmailHeader};
+use crate::issue_rendering::RenderedIssue;
+use crate::metrics::metrics;
+use crate::startup::{get_connection_pool, ApplicationBaseUrl, HmacSecret};
+use crate::worker_heartbeat::{Heartbeat, WorkerKind};
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue
+}
+
+/// Final outcome of the delivery of an issue to a subscriber.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryOutcome {
+    Delivered,
+    // every attempt failed
+    Failed,
+    // the stored email address is no longer valid,
+    // or the subscriber left in the meantime
+    Skipped
+}
+
+impl DeliveryOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Delivered => "delivered",
+            DeliveryOutcome::Failed => "failed",
+            DeliveryOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+/*
+    pick a single task from the queue and try to deliver it
+
+    the row stays locked until the attempt is over, with `SKIP LOCKED`
+    concurrent workers never pick the same (issue, subscriber) pair
+
+    every issue carries a signed unsubscribe link, both in its
+    content and in the `List-Unsubscribe` headers
+ */
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id = tracing::field::Empty,
+        subscriber_email = tracing::field::Empty,
+        attempt = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &WorkerSettings,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_task(pool).await?;
+    if task.is_none() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let (mut transaction, task) = task.unwrap();
+    let attempt = task.n_retries + 1;
+    Span::current()
+        .record("newsletter_issue_id", display(task.newsletter_issue_id))
+        .record("subscriber_email", display(&task.subscriber_email))
+        .record("attempt", attempt);
+
+    match attempt_delivery(pool, email_client, base_url, hmac_secret, &task).await {
+        Ok(AttemptOutcome::Delivered) => {
+            record_outcome(&mut transaction, &task, DeliveryOutcome::Delivered, attempt, None).await?;
+            delete_task(transaction, &task).await?;
+        }
+        Ok(AttemptOutcome::Skipped(reason)) => {
+            tracing::info!(reason = %reason, "skipping a subscriber");
+            record_outcome(&mut transaction, &task, DeliveryOutcome::Skipped, attempt, Some(&reason)).await?;
+            delete_task(transaction, &task).await?;
+        }
+        // whatever failed, the task goes back to the queue with a backoff,
+        // it must not be picked up again right away and forever
+        Err(e) if attempt < settings.max_attempts => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "failed to deliver issue to a confirmed subscriber. retrying later"
+            );
+            let execute_after = settings.backoff(attempt);
+            reschedule_task(transaction, &task, execute_after).await?;
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "failed to deliver issue to a confirmed subscriber. giving up"
+            );
+            let last_error = format!("{:?}", e);
+            record_outcome(&mut transaction, &task, DeliveryOutcome::Failed, attempt, Some(&last_error)).await?;
+            delete_task(transaction, &task).await?;
+        }
+    }
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+enum AttemptOutcome {
+    Delivered,
+    // why there was nothing to deliver
+    Skipped(String)
+}
+
+/*
+    a single attempt at delivering the issue of a task, any error
+    is worth another attempt later on
+ */
+async fn attempt_delivery(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    task: &Task
+) -> Result<AttemptOutcome, anyhow::Error> {
+    let issue = get_issue(pool, task.newsletter_issue_id).await?;
+    // the subscriber may have unsubscribed after the issue was queued
+    let subscriber_id = match get_confirmed_subscriber_id(pool, &task.subscriber_email, issue.list_id).await? {
+        Some(subscriber_id) => subscriber_id,
+        None => return Ok(AttemptOutcome::Skipped("the subscriber is no longer confirmed".into()))
+    };
+    // a single bad row must not prevent everybody else
+    // from receiving the issue
+    let email = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => email,
+        Err(e) => return Ok(AttemptOutcome::Skipped(e.to_string()))
+    };
+
+    let unsubscribe_link = unsubscribe_link(base_url, hmac_secret, subscriber_id, issue.list_id);
+    let content = RenderedIssue {
+        html: issue.html_content,
+        text: issue.text_content
+    }
+    .with_unsubscribe_link(&unsubscribe_link);
+    email_client
+        .send_email_with_headers(
+            &email,
+            &issue.title,
+            &content.html,
+            &content.text,
+            &unsubscribe_headers(&unsubscribe_link)
+        )
+        .await
+        .context("failed to send the issue")?;
+
+    Ok(AttemptOutcome::Delivered)
+}
+
+impl WorkerSettings {
+    /*
+        exponential backoff: the delay doubles after every failed attempt,
+        up to one hour
+     */
+    pub fn backoff(&self, attempt: i16) -> Duration {
+        let max_backoff = Duration::from_secs(60 * 60);
+        let exponent = (attempt.max(1) - 1).min(20) as u32;
+        Duration::from_millis(self.base_backoff_milliseconds)
+            .saturating_mul(2u32.pow(exponent))
+            .min(max_backoff)
+    }
+}
+
+fn unsubscribe_link(
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    subscriber_id: Uuid,
+    list_id: Uuid
+) -> String {
+    let token = UnsubscribeToken::generate(subscriber_id, list_id, &hmac_secret.0);
+    format!("{}/subscriptions/unsubscribe?token={}", base_url.0, token.as_ref())
+}
+
+/*
+    `List-Unsubscribe-Post` (RFC 8058) tells mail clients they can
+    unsubscribe with a single POST to the link, without asking the user
+    to visit our page
+ */
+fn unsubscribe_headers(unsubscribe_link: &str) -> [EmailHeader; 2] {
+    [
+        EmailHeader {
+            name: "List-Unsubscribe",
+            value: format!("<{}>", unsubscribe_link)
+        },
+        EmailHeader {
+            name: "List-Unsubscribe-Post",
+            value: "List-Unsubscribe=One-Click".into()
+        }
+    ]
+}
+
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(mut transaction: PgTransaction, task: &Task) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+        )
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    mut transaction: PgTransaction,
+    task: &Task,
+    execute_after: Duration
+) -> Result<(), anyhow::Error> {
+    // the interval is computed by Postgres, so that every replica
+    // agrees on the clock being used
+    let execute_after = PgInterval::try_from(execute_after)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + $3
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        execute_after
+        )
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_outcome(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    outcome: DeliveryOutcome,
+    n_attempts: i16,
+    last_error: Option<&str>
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_outcomes (
+            newsletter_issue_id,
+            subscriber_email,
+            outcome,
+            n_attempts,
+            last_error,
+            recorded_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        outcome.as_str(),
+        n_attempts,
+        last_error
+        )
+        .execute(transaction)
+        .await
+        .context("failed to record the delivery outcome")?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber_id(
+    pool: &PgPool,
+    email: &str,
+    list_id: Uuid
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT s.id
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        WHERE
+            s.email = $1 AND
+            m.list_id = $2 AND
+            m.status = 'confirmed'
+        "#,
+        email,
+        list_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(subscriber.map(|s| s.id))
+}
+
+struct NewsletterIssue {
+    list_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT list_id, title, text_content, html_content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1
+        "#,
+        issue_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(issue)
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    settings: WorkerSettings,
+    base_url: ApplicationBaseUrl,
+    hmac_secret: HmacSecret
+) -> Result<(), anyhow::Error> {
+    let mut heartbeat = Heartbeat::new(WorkerKind::DeliveryWorker);
+    loop {
+        heartbeat.beat(&pool).await;
+        metrics().observe_pool("delivery_worker", &pool);
+        // confirmation emails are short and someone may be waiting for them
+        let outcome = match try_send_confirmation_email(&pool, &email_client, &settings, &base_url).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                try_execute_task(&pool, &email_client, &settings, &base_url, &hmac_secret).await
+            }
+            outcome => outcome
+        };
+        match outcome {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+/*
+    background worker draining the confirmation email queue, then
+    the issue delivery queue,
+    it runs until the process is stopped
+ */
+pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration
+        .email_client
+        .client()
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("invalid email client configuration")?;
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.worker,
+        ApplicationBaseUrl(configuration.application.base_url),
+        HmacSecret(configuration.application.hmac_secret)
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::configuration::WorkerSettings;
+
+    fn settings(base_backoff_milliseconds: u64) -> WorkerSettings {
+        WorkerSettings {
+            max_attempts: 5,
+            base_backoff_milliseconds
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_after_every_attempt() {
+        let settings = settings(1000);
+        assert_eq!(settings.backoff(1), Duration::from_secs(1));
+        assert_eq!(settings.backoff(2), Duration::from_secs(2));
+        assert_eq!(settings.backoff(3), Duration::from_secs(4));
+        assert_eq!(settings.backoff(4), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn backoff_is_capped_to_one_hour() {
+        let settings = settings(1000);
+        assert_eq!(settings.backoff(i16::MAX), Duration::from_secs(60 * 60));
+    }
+}
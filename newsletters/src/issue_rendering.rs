@@ -0,0 +1,316 @@
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+const TITLE_PLACEHOLDER: &str = "{{title}}";
+const CONTENT_PLACEHOLDER: &str = "{{content}}";
+
+/// Layout used when the configuration does not provide one.
+pub const DEFAULT_LAYOUT: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{{title}}</title>
+</head>
+<body>
+{{content}}
+</body>
+</html>"#;
+
+/// Both bodies of an issue, as they are stored and sent.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RenderedIssue {
+    pub html: String,
+    pub text: String
+}
+
+impl RenderedIssue {
+    /*
+        add the unsubscribe link of a subscriber at the end of both bodies
+
+        issues rendered with a layout are full documents: the link
+        must go inside their `<body>`, not after it
+     */
+    pub fn with_unsubscribe_link(mut self, unsubscribe_link: &str) -> Self {
+        let footer = format!(
+            "<p><a href=\"{}\">Unsubscribe</a></p>",
+            htmlescape::encode_minimal(unsubscribe_link)
+        );
+        match self.html.rfind("</body>") {
+            Some(i) => self.html.insert_str(i, &format!("{}\n", footer)),
+            None => self.html.push_str(&footer)
+        }
+        self.text = format!("{}\n\nUnsubscribe: {}", self.text, unsubscribe_link);
+        self
+    }
+}
+
+/*
+    turns the Markdown written by editors into the two bodies of an issue
+
+    the HTML is sanitized, so that a pasted snippet cannot smuggle scripts,
+    styles or event handlers, then wrapped in the layout; remote images are
+    kept, editors are expected to embed pictures hosted elsewhere
+ */
+#[derive(Debug, Clone)]
+pub struct IssueRenderer {
+    // the layout, split around the content placeholder
+    before_content: String,
+    after_content: String
+}
+
+impl IssueRenderer {
+    /// The layout must contain `{{content}}` exactly once,
+    /// it may contain `{{title}}` anywhere.
+    pub fn new(layout: &str) -> Result<Self, String> {
+        let (before_content, after_content) = layout
+            .split_once(CONTENT_PLACEHOLDER)
+            .ok_or_else(|| format!("the issue layout has no {} placeholder", CONTENT_PLACEHOLDER))?;
+        if after_content.contains(CONTENT_PLACEHOLDER) {
+            return Err(format!("the issue layout has more than one {} placeholder", CONTENT_PLACEHOLDER));
+        }
+
+        Ok(Self {
+            before_content: before_content.to_owned(),
+            after_content: after_content.to_owned()
+        })
+    }
+
+    pub fn render(&self, title: &str, markdown: &str) -> RenderedIssue {
+        let mut content = String::new();
+        pulldown_cmark::html::push_html(&mut content, Parser::new_ext(markdown, markdown_options()));
+        let content = ammonia::clean(&content);
+
+        // the title is substituted in the layout only, never in the content
+        let title = htmlescape::encode_minimal(title);
+        let html = format!(
+            "{}{}{}",
+            self.before_content.replace(TITLE_PLACEHOLDER, &title),
+            content,
+            self.after_content.replace(TITLE_PLACEHOLDER, &title)
+        );
+
+        RenderedIssue {
+            html,
+            text: plain_text(markdown)
+        }
+    }
+}
+
+impl Default for IssueRenderer {
+    fn default() -> Self {
+        Self::new(DEFAULT_LAYOUT).expect("the default layout is valid")
+    }
+}
+
+fn markdown_options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH
+}
+
+/*
+    plain-text alternative, meant to read well as is: headings are
+    underlined, links are followed by their url, raw HTML is dropped
+ */
+fn plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    // one entry per open list, with the number of the next item if ordered
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut link_urls: Vec<String> = Vec::new();
+    let mut heading_start = 0;
+    let mut in_code_block = false;
+    let mut quote_depth = 0;
+
+    for event in Parser::new_ext(markdown, markdown_options()) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => heading_start = text.len(),
+            Event::End(TagEnd::Heading(level)) => {
+                let length = text[heading_start..].chars().count();
+                match level {
+                    HeadingLevel::H1 => text.push_str(&format!("\n{}", "=".repeat(length))),
+                    HeadingLevel::H2 => text.push_str(&format!("\n{}", "-".repeat(length))),
+                    _ => {}
+                }
+                text.push_str("\n\n");
+            }
+            Event::Start(Tag::Paragraph) => text.push_str(&"> ".repeat(quote_depth)),
+            Event::End(TagEnd::Paragraph) => {
+                // items of a loose list are paragraphs as well
+                text.push_str(if lists.is_empty() { "\n\n" } else { "\n" })
+            }
+            Event::Start(Tag::BlockQuote(_)) => quote_depth += 1,
+            Event::End(TagEnd::BlockQuote(_)) => quote_depth -= 1,
+            Event::Start(Tag::List(first_number)) => {
+                if !lists.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                lists.push(first_number);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    text.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- ")
+                }
+            }
+            Event::End(TagEnd::Item) if !text.ends_with('\n') => text.push('\n'),
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                text.push('\n');
+            }
+            Event::Start(Tag::Link { dest_url, .. }) | Event::Start(Tag::Image { dest_url, .. }) => {
+                link_urls.push(dest_url.into_string())
+            }
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                if let Some(url) = link_urls.pop() {
+                    // autolinks already show their url
+                    if !text.ends_with(&url) {
+                        text.push_str(&format!(" ({})", url));
+                    }
+                }
+            }
+            Event::End(TagEnd::TableHead) | Event::End(TagEnd::TableRow) => text.push('\n'),
+            Event::End(TagEnd::TableCell) => text.push_str(" | "),
+            Event::End(TagEnd::Table) => text.push('\n'),
+            Event::Text(s) if in_code_block => {
+                for line in s.lines() {
+                    text.push_str(&format!("    {}\n", line));
+                }
+            }
+            Event::Text(s) | Event::Code(s) => text.push_str(&s),
+            Event::SoftBreak | Event::HardBreak => {
+                text.push('\n');
+                text.push_str(&"> ".repeat(quote_depth));
+            }
+            Event::Rule => text.push_str("----------\n\n"),
+            _ => {}
+        }
+    }
+
+    // table rows end with a dangling separator
+    let text = text.replace(" | \n", "\n");
+    text.trim_end().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IssueRenderer, RenderedIssue};
+
+    fn render(markdown: &str) -> RenderedIssue {
+        IssueRenderer::new("<main>{{content}}</main>").unwrap().render("Title", markdown)
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let issue = render("# Hello\n\nSome *emphasis*.");
+
+        assert_eq!(issue.html, "<main><h1>Hello</h1>\n<p>Some <em>emphasis</em>.</p>\n</main>");
+    }
+
+    #[test]
+    fn scripts_and_event_handlers_are_stripped() {
+        let issue = render("Hi <script>alert(1)</script><img src=\"x.png\" onerror=\"alert(2)\">");
+
+        assert!(!issue.html.contains("script"));
+        assert!(!issue.html.contains("onerror"));
+        assert!(issue.html.contains("x.png"));
+    }
+
+    #[test]
+    fn the_title_is_escaped_in_the_layout() {
+        let renderer = IssueRenderer::new("<title>{{title}}</title>{{content}}").unwrap();
+
+        let issue = renderer.render("Cats & <dogs>", "{{title}}");
+
+        assert!(issue.html.starts_with("<title>Cats &amp; &lt;dogs&gt;</title>"));
+        // placeholders typed by the editor are left alone
+        assert!(issue.html.contains("<p>{{title}}</p>"));
+    }
+
+    #[test]
+    fn a_layout_without_a_content_placeholder_is_rejected() {
+        assert!(IssueRenderer::new("<html>{{title}}</html>").is_err());
+        assert!(IssueRenderer::new("{{content}}{{content}}").is_err());
+    }
+
+    #[test]
+    fn the_default_layout_is_valid() {
+        let issue = IssueRenderer::default().render("Title", "Hello");
+
+        assert!(issue.html.contains("<title>Title</title>"));
+        assert!(issue.html.contains("<p>Hello</p>"));
+    }
+
+    #[test]
+    fn the_plain_text_keeps_the_structure_readable() {
+        let markdown = "\
+# Weekly digest
+
+Read [our blog](https://example.com/blog) or <https://example.com>.
+
+## Topics
+
+- Rust
+- Postgres
+  1. indexes
+  2. locks
+
+> Quoted
+> text
+
+    let x = 1;
+
+<div>raw html</div>
+";
+
+        let issue = render(markdown);
+
+        assert_eq!(
+            issue.text,
+            "\
+Weekly digest
+=============
+
+Read our blog (https://example.com/blog) or https://example.com.
+
+Topics
+------
+
+- Rust
+- Postgres
+  1. indexes
+  2. locks
+
+> Quoted
+> text
+
+    let x = 1;"
+        );
+    }
+
+    #[test]
+    fn tables_are_rendered_as_rows_of_cells() {
+        let issue = render("| a | b |\n|---|---|\n| 1 | 2 |\n");
+
+        assert_eq!(issue.text, "a | b\n1 | 2");
+    }
+
+    #[test]
+    fn the_unsubscribe_link_goes_inside_the_body() {
+        let issue = IssueRenderer::default()
+            .render("Title", "Hello")
+            .with_unsubscribe_link("https://example.com/unsubscribe?token=a&b");
+
+        assert!(issue
+            .html
+            .ends_with("<p><a href=\"https://example.com/unsubscribe?token=a&amp;b\">Unsubscribe</a></p>\n</body>\n</html>"));
+        assert!(issue.text.ends_with("\n\nUnsubscribe: https://example.com/unsubscribe?token=a&b"));
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::configuration::Settings;
+use crate::metrics::metrics;
+use crate::startup::get_connection_pool;
+use crate::worker_heartbeat::{Heartbeat, WorkerKind};
+
+pub enum SchedulerOutcome {
+    IssueEnqueued,
+    NothingDue
+}
+
+/*
+    queue the deliveries of a single issue whose time has come
+
+    the issue row stays locked until its deliveries are queued and its
+    status flipped, all in one transaction: with `SKIP LOCKED` concurrent
+    schedulers never pick the same issue, and once committed the issue
+    is no longer 'scheduled' for anybody
+ */
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id = tracing::field::Empty),
+    err
+)]
+pub async fn try_enqueue_due_issue(pool: &PgPool) -> Result<SchedulerOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let issue = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, list_id
+        FROM newsletter_issues
+        WHERE
+            status = 'scheduled' AND
+            scheduled_at <= now()
+        ORDER BY scheduled_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Ok(SchedulerOutcome::NothingDue)
+    };
+    Span::current().record("newsletter_issue_id", display(issue.newsletter_issue_id));
+
+    enqueue_delivery_tasks(&mut transaction, issue.newsletter_issue_id, issue.list_id).await?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = 'enqueued',
+            published_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue.newsletter_issue_id
+        )
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    tracing::info!("scheduled issue enqueued for delivery");
+
+    Ok(SchedulerOutcome::IssueEnqueued)
+}
+
+/*
+    one delivery task per confirmed member of the list,
+    only confirmed subscribers ever get newsletters
+ */
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    list_id: Uuid
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, s.email
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        WHERE
+            m.list_id = $2 AND
+            m.status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+        list_id
+        )
+        .execute(transaction)
+        .await?;
+
+    Ok(())
+}
+
+async fn scheduler_loop(pool: PgPool) -> Result<(), anyhow::Error> {
+    let mut heartbeat = Heartbeat::new(WorkerKind::IssueScheduler);
+    loop {
+        heartbeat.beat(&pool).await;
+        metrics().observe_pool("issue_scheduler", &pool);
+        match try_enqueue_due_issue(&pool).await {
+            Ok(SchedulerOutcome::NothingDue) => {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+            Err(_) => {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Ok(SchedulerOutcome::IssueEnqueued) => {}
+        }
+    }
+}
+
+/*
+    background loop queuing scheduled issues once they are due,
+    it runs until the process is stopped
+ */
+pub async fn run_scheduler_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    scheduler_loop(connection_pool).await
+}
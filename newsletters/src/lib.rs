@@ -2,4 +2,24 @@ pub mod configuration;
 pub mod startup;
 pub mod routes;
 pub mod domain;
+pub mod domain_policy;
 pub mod telemetry;
+pub mod email_client;
+pub mod utils;
+pub mod problem_details;
+pub mod rate_limiting;
+pub mod metrics;
+pub mod signup_protection;
+pub mod mailing_list;
+pub mod issue_rendering;
+pub mod subscriber_filter;
+pub mod subscriber_export;
+pub mod subscriber_import;
+pub mod authentication;
+pub mod session_state;
+pub mod session_store;
+pub mod idempotency;
+pub mod confirmation_email_worker;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod worker_heartbeat;
@@ -0,0 +1,48 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// List targeted by the requests that do not name one,
+/// every subscriber belonged to it before lists existed.
+pub const DEFAULT_LIST_SLUG: &str = "newsletter";
+
+#[derive(serde::Serialize, Debug)]
+pub struct MailingList {
+    #[serde(skip)]
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>
+}
+
+/*
+    look a list up by the slug found in a request,
+    the default list if the request did not name one
+ */
+#[tracing::instrument(name = "Get mailing list", skip(executor))]
+pub async fn get_list_by_slug<'e>(
+    executor: impl PgExecutor<'e>,
+    slug: Option<&str>
+) -> Result<Option<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"SELECT list_id, slug, name, created_at FROM lists WHERE slug = $1"#,
+        slug.unwrap_or(DEFAULT_LIST_SLUG)
+        )
+        .fetch_optional(executor)
+        .await
+}
+
+#[tracing::instrument(name = "Get mailing list", skip(executor))]
+pub async fn get_list<'e>(
+    executor: impl PgExecutor<'e>,
+    list_id: Uuid
+) -> Result<MailingList, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"SELECT list_id, slug, name, created_at FROM lists WHERE list_id = $1"#,
+        list_id
+        )
+        .fetch_one(executor)
+        .await
+}
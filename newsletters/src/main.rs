@@ -1,10 +1,111 @@
 
-use std::net::TcpListener;
-use sqlx::postgres::PgPoolOptions;
-use zero2prod::startup::run;
-use zero2prod::configuration::get_configuration;
+use std::fmt::{Debug, Display};
+use std::path::PathBuf;
+use anyhow::Context;
+use clap::Parser;
+use tokio::task::JoinError;
+use secrecy::{ExposeSecret, Secret};
+use zero2prod::authentication::{create_admin, PASSWORD_LENGTH};
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod::issue_scheduler::run_scheduler_until_stopped;
+use zero2prod::mailing_list::get_list_by_slug;
+use zero2prod::startup::{get_connection_pool, run_admin_until_stopped, run_api_until_stopped};
+use zero2prod::subscriber_export::write_subscribers_csv;
+use zero2prod::subscriber_filter::{FilterParameters, SubscriberFilter};
+use zero2prod::subscriber_import::{import_subscribers, parse_csv, parse_json, ImportStatus};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
+#[derive(Parser)]
+#[command(about = "Email newsletter service")]
+struct Cli {
+    /// Which parts of the service this process runs
+    #[arg(long, value_enum, env = "APP_RUN_MODE", default_value_t = RunMode::All)]
+    mode: RunMode,
+    /// One-off administrative task, the service is not started
+    #[command(subcommand)]
+    command: Option<Command>
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Write the subscribers as CSV
+    ExportSubscribers(ExportArgs),
+    /// Import subscribers from a CSV file or a JSON array, the report is written as JSON
+    ImportSubscribers(ImportArgs),
+    /// Create an admin account, or reset the password of an existing one;
+    /// the admin has to choose a new password when they first log in
+    CreateAdmin(CreateAdminArgs)
+}
+
+#[derive(clap::Args)]
+struct CreateAdminArgs {
+    #[arg(long)]
+    username: String,
+    /// Read from the standard input if omitted
+    #[arg(long, env = "APP_ADMIN_PASSWORD", hide_env_values = true)]
+    password: Option<Secret<String>>
+}
+
+#[derive(clap::Args)]
+struct ExportArgs {
+    /// Slug of the list to export, the default list if omitted
+    #[arg(long)]
+    list: Option<String>,
+    /// Only export subscribers with this status
+    #[arg(long)]
+    status: Option<String>,
+    /// Only export subscribers who signed up at or after this RFC 3339 date
+    #[arg(long)]
+    subscribed_after: Option<String>,
+    /// Only export subscribers who signed up before this RFC 3339 date
+    #[arg(long)]
+    subscribed_before: Option<String>,
+    /// Only export subscribers whose email or name contains this text
+    #[arg(long)]
+    search: Option<String>,
+    /// Destination file, the standard output if omitted
+    #[arg(long, short)]
+    output: Option<PathBuf>
+}
+
+#[derive(clap::Args)]
+struct ImportArgs {
+    /// CSV file with `email` and `name` columns, or JSON array of `{"email", "name"}` objects
+    file: PathBuf,
+    /// Whether the imported subscribers already confirmed their subscription
+    #[arg(long, value_enum)]
+    status: ImportStatusArg,
+    /// Slug of the list to import into, the default list if omitted
+    #[arg(long)]
+    list: Option<String>,
+    /// Format of the file, guessed from its extension if omitted
+    #[arg(long, value_enum)]
+    format: Option<ImportFormat>
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ImportStatusArg {
+    Confirmed,
+    /// A confirmation email is sent to each of them by the delivery worker
+    PendingConfirmation
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ImportFormat {
+    Csv,
+    Json
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum RunMode {
+    /// Serve the HTTP API only
+    Api,
+    /// Queue the scheduled issues and drain the delivery queue only
+    Worker,
+    /// Serve the HTTP API and run the background loops in the same process
+    All
+}
 
 /*
     asynchronous runtime for webserver
@@ -17,18 +118,172 @@ use zero2prod::telemetry::{get_subscriber, init_subscriber};
     and wait for future correspondin the that task
  */
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    if let Some(command) = cli.command {
+        // the standard output may be carrying the result of the command
+        let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stderr);
+        init_subscriber(subscriber);
+
+        let configuration = get_configuration().expect("failed to read configuration");
+        return match command {
+            Command::ExportSubscribers(args) => export_subscribers(configuration, args).await,
+            Command::ImportSubscribers(args) => import_subscribers_from_file(configuration, args).await,
+            Command::CreateAdmin(args) => create_admin_account(configuration, args).await
+        };
+    }
 
     let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
     init_subscriber(subscriber);
 
     let configuration = get_configuration().expect("failed to read configuration");
 
-    let address = format!("{}:{}", configuration.application.host, configuration.application.port);
-    let connection_pool = PgPoolOptions::new()
-        // `connect_lazy_with` instead of `connect_lazy`
-        .connect_lazy_with(configuration.database.with_db());
-    
-    let listener = TcpListener::bind(address)?;
-    run(listener, connection_pool)?.await
+    match cli.mode {
+        RunMode::Api => run_api_until_stopped(configuration).await?,
+        RunMode::Worker => {
+            let scheduler_task = tokio::spawn(run_scheduler_until_stopped(configuration.clone()));
+            let worker_task = tokio::spawn(run_worker_until_stopped(configuration.clone()));
+            // the metrics of the background loops, the API serves them otherwise
+            let admin_task = tokio::spawn(run_admin_until_stopped(configuration));
+
+            tokio::select! {
+                o = scheduler_task => report_exit("Issue scheduler", o),
+                o = worker_task => report_exit("Background worker", o),
+                o = admin_task => report_exit("Admin server", o),
+            };
+        }
+        RunMode::All => {
+            let api_task = tokio::spawn(run_api_until_stopped(configuration.clone()));
+            let scheduler_task = tokio::spawn(run_scheduler_until_stopped(configuration.clone()));
+            let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+
+            // the process stops as soon as any of them does
+            tokio::select! {
+                o = api_task => report_exit("API", o),
+                o = scheduler_task => report_exit("Issue scheduler", o),
+                o = worker_task => report_exit("Background worker", o),
+            };
+        }
+    }
+
+    Ok(())
+}
+
+async fn export_subscribers(configuration: Settings, args: ExportArgs) -> anyhow::Result<()> {
+    let filter = SubscriberFilter::try_from(FilterParameters {
+        list: args.list,
+        status: args.status,
+        subscribed_after: args.subscribed_after,
+        subscribed_before: args.subscribed_before,
+        search: args.search
+    })
+    .map_err(|invalid_params| {
+        let reasons: Vec<_> = invalid_params
+            .iter()
+            .map(|p| format!("--{}: {}", p.name.replace('_', "-"), p.reason))
+            .collect();
+        anyhow::anyhow!("invalid filters\n{}", reasons.join("\n"))
+    })?;
+    let pool = get_connection_pool(&configuration.database);
+
+    match args.output {
+        Some(path) => {
+            let file = tokio::fs::File::create(&path)
+                .await
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            write_subscribers_csv(pool, filter, file).await
+        }
+        None => write_subscribers_csv(pool, filter, tokio::io::stdout()).await
+    }
+}
+
+async fn import_subscribers_from_file(configuration: Settings, args: ImportArgs) -> anyhow::Result<()> {
+    let format = match args.format {
+        Some(format) => format,
+        None => match args.file.extension().and_then(|e| e.to_str()) {
+            Some("csv") => ImportFormat::Csv,
+            Some("json") => ImportFormat::Json,
+            _ => anyhow::bail!("cannot guess the format of {}, use --format", args.file.display())
+        }
+    };
+    let status = match args.status {
+        ImportStatusArg::Confirmed => ImportStatus::Confirmed,
+        ImportStatusArg::PendingConfirmation => ImportStatus::PendingConfirmation
+    };
+
+    let body = tokio::fs::read(&args.file)
+        .await
+        .with_context(|| format!("failed to read {}", args.file.display()))?;
+    let rows = match format {
+        ImportFormat::Csv => parse_csv(&body),
+        ImportFormat::Json => parse_json(&body)
+    }
+    .map_err(|e| anyhow::anyhow!(e))?;
+
+    let pool = get_connection_pool(&configuration.database);
+    let list = get_list_by_slug(&pool, args.list.as_deref())
+        .await
+        .context("failed to fetch the mailing list")?
+        .with_context(|| format!("there is no list named '{}'", args.list.unwrap_or_default()))?;
+    let domain_policy = configuration.domain_policy.policy()
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("invalid domain policy")?;
+    let report = import_subscribers(&pool, rows, list.list_id, status, &domain_policy).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    Ok(())
+}
+
+async fn create_admin_account(configuration: Settings, args: CreateAdminArgs) -> anyhow::Result<()> {
+    let password = match args.password {
+        Some(password) => password,
+        None => {
+            let mut password = String::new();
+            std::io::stdin()
+                .read_line(&mut password)
+                .context("failed to read the password from the standard input")?;
+            Secret::new(password.trim_end_matches(['\r', '\n']).to_owned())
+        }
+    };
+    if !PASSWORD_LENGTH.contains(&password.expose_secret().chars().count()) {
+        anyhow::bail!(
+            "the password must be between {} and {} characters long",
+            PASSWORD_LENGTH.start(),
+            PASSWORD_LENGTH.end()
+        );
+    }
+
+    let pool = get_connection_pool(&configuration.database);
+    let user_id = create_admin(&args.username, password, &pool).await?;
+    println!("{}", user_id);
+
+    Ok(())
+}
+
+fn report_exit(
+    task_name: &str,
+    outcome: Result<Result<(), impl Debug + Display>, JoinError>
+) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
 }
@@ -0,0 +1,183 @@
+use std::time::Instant;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder
+};
+use sqlx::PgPool;
+
+// one registry for the whole process: the API, the scheduler and the
+// delivery worker may run side by side and are scraped together
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// What became of a signup request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionOutcome {
+    // a confirmation email is on its way
+    Accepted,
+    ValidationFailed,
+    // the address already confirmed its subscription to the list
+    Duplicate,
+    DbError,
+    EmailError,
+    // taken for the work of a bot, see `SignupProtection`
+    BotRejected
+}
+
+impl SubscriptionOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionOutcome::Accepted => "accepted",
+            SubscriptionOutcome::ValidationFailed => "validation_failed",
+            SubscriptionOutcome::Duplicate => "duplicate",
+            SubscriptionOutcome::DbError => "db_error",
+            SubscriptionOutcome::EmailError => "email_error",
+            SubscriptionOutcome::BotRejected => "bot_rejected"
+        }
+    }
+}
+
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    subscriptions: IntCounterVec,
+    emails_sent: IntCounterVec
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests served"),
+            &["method", "route", "status"]
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "time spent serving HTTP requests"),
+            &["method", "route", "status"]
+        )
+        .unwrap();
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "connections open in a Postgres pool"),
+            &["pool", "state"]
+        )
+        .unwrap();
+        let subscriptions = IntCounterVec::new(
+            Opts::new("subscriptions_total", "signup requests, by outcome"),
+            &["outcome"]
+        )
+        .unwrap();
+        let emails_sent = IntCounterVec::new(
+            Opts::new("emails_sent_total", "emails handed to the email transport, by result"),
+            &["transport", "result"]
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_request_duration.clone())).unwrap();
+        registry.register(Box::new(db_pool_connections.clone())).unwrap();
+        registry.register(Box::new(subscriptions.clone())).unwrap();
+        registry.register(Box::new(emails_sent.clone())).unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            subscriptions,
+            emails_sent
+        }
+    }
+
+    pub fn record_subscription(&self, outcome: SubscriptionOutcome) {
+        self.subscriptions.with_label_values(&[outcome.as_str()]).inc();
+    }
+
+    pub fn record_email_sent(&self, transport: &str, succeeded: bool) {
+        let result = if succeeded { "success" } else { "failure" };
+        self.emails_sent.with_label_values(&[transport, result]).inc();
+    }
+
+    /*
+        usage of a connection pool, as of now
+
+        sqlx keeps no statistics of its own, the gauges are only as fresh
+        as the last call: at scrape time for the API pool, on every
+        iteration for the pools of the background loops
+     */
+    pub fn observe_pool(&self, name: &str, pool: &PgPool) {
+        let size = i64::from(pool.size());
+        let idle = pool.num_idle() as i64;
+        self.db_pool_connections.with_label_values(&[name, "idle"]).set(idle);
+        self.db_pool_connections.with_label_values(&[name, "in_use"]).set(size - idle);
+    }
+
+    // the Prometheus text exposition format
+    pub fn encode(&self) -> Result<(String, String), prometheus::Error> {
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        encoder.encode(&self.registry.gather(), &mut buffer)?;
+        let body = String::from_utf8(buffer).expect("the text format is UTF-8");
+        Ok((encoder.format_type().to_owned(), body))
+    }
+}
+
+/*
+    count and time every request, by route and status
+
+    the route is the pattern it matched, e.g. `/admin/lists/{slug}`, so that
+    the number of series does not grow with the ids found in paths; requests
+    matching no route at all are counted together
+ */
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let started_at = Instant::now();
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".into());
+
+    let outcome = next.call(req).await;
+    // errors from the middlewares, e.g. rate limiting, become responses further out
+    let status = match &outcome {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code()
+    };
+
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    let metrics = metrics();
+    metrics.http_requests.with_label_values(&labels).inc();
+    metrics
+        .http_request_duration
+        .with_label_values(&labels)
+        .observe(started_at.elapsed().as_secs_f64());
+
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Metrics, SubscriptionOutcome};
+
+    #[test]
+    fn recorded_values_show_up_in_the_text_format() {
+        let metrics = Metrics::new();
+        metrics.record_subscription(SubscriptionOutcome::Accepted);
+        metrics.record_subscription(SubscriptionOutcome::Accepted);
+        metrics.record_email_sent("smtp", false);
+
+        let (content_type, body) = metrics.encode().unwrap();
+
+        assert!(content_type.starts_with("text/plain"));
+        assert!(body.contains(r#"subscriptions_total{outcome="accepted"} 2"#), "{}", body);
+        assert!(body.contains(r#"emails_sent_total{result="failure",transport="smtp"} 1"#), "{}", body);
+    }
+}
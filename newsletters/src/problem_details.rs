@@ -0,0 +1,68 @@
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+
+/*
+    error body following RFC 7807, served as `application/problem+json`
+    so that clients can tell our errors apart from any other payload
+ */
+#[derive(serde::Serialize, Debug)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    // extension member, one entry per rejected field
+    #[serde(rename = "invalid-params", skip_serializing_if = "Vec::is_empty")]
+    invalid_params: Vec<InvalidParam>,
+    // extension member, a corrected value the client may offer to resubmit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestion: Option<String>
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct InvalidParam {
+    pub name: &'static str,
+    // stable identifier of the violated rule, for clients to match on
+    pub code: &'static str,
+    // human readable explanation
+    pub reason: String
+}
+
+impl ProblemDetails {
+    /// A problem with no further semantics than its status code,
+    /// hence the `about:blank` type.
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or("Unknown Error"),
+            status: status.as_u16(),
+            detail: None,
+            invalid_params: Vec::new(),
+            suggestion: None
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_invalid_param(mut self, invalid_param: InvalidParam) -> Self {
+        self.invalid_params.push(invalid_param);
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        HttpResponse::build(status)
+            .content_type("application/problem+json")
+            .json(self)
+    }
+}
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderMap, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::web;
+
+use crate::problem_details::ProblemDetails;
+
+// how often the buckets of clients that went quiet are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+// bound on the memory used by the buckets, whatever the number of clients
+const MAX_BUCKETS: usize = 100_000;
+
+/// Token bucket applied to each client of a route.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RouteRateLimit {
+    pub path: String,
+    // any method if omitted
+    pub method: Option<String>,
+    // requests a client can burst before being throttled
+    pub capacity: u32,
+    // tokens given back to each client every minute
+    pub refill_per_minute: u32
+}
+
+/*
+    address, or range of addresses in CIDR notation, of a reverse
+    proxy whose `X-Forwarded-For` header we believe
+ */
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix_length: u8
+}
+
+impl TryFrom<String> for TrustedProxy {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("{} is neither an IP address nor a CIDR range", value);
+        let (address, prefix_length) = match value.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (value.as_str(), None)
+        };
+        let network: IpAddr = address.parse().map_err(|_| invalid())?;
+        let max_length = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_length = match prefix_length {
+            Some(prefix_length) => prefix_length
+                .parse()
+                .ok()
+                .filter(|l| *l <= max_length)
+                .ok_or_else(invalid)?,
+            None => max_length
+        };
+
+        Ok(Self { network, prefix_length })
+    }
+}
+
+impl TrustedProxy {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_length as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_length as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant
+}
+
+struct Buckets {
+    // keyed by the index of the route limit and the client address
+    buckets: HashMap<(usize, IpAddr), Bucket>,
+    pruned_at: Instant
+}
+
+impl Buckets {
+    // a full bucket is the same as no bucket at all
+    fn prune(&mut self, routes: &[RouteRateLimit], now: Instant) {
+        self.buckets.retain(|(i, _), bucket| {
+            let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+            let rate = routes[*i].refill_per_minute as f64 / 60.0;
+            bucket.tokens + elapsed * rate < routes[*i].capacity as f64
+        });
+        self.pruned_at = now;
+    }
+
+    /*
+        forget the tenth of the buckets that were refilled the longest ago
+
+        their clients start over with a full bucket: a lenient outcome,
+        but turning new clients away would let a single one holding many
+        addresses lock everybody else out
+     */
+    fn evict_least_recently_refilled(&mut self) {
+        let mut refilled_at: Vec<Instant> = self.buckets.values().map(|b| b.updated_at).collect();
+        let n_evicted = (refilled_at.len() / 10).max(1);
+        let (_, threshold, _) = refilled_at.select_nth_unstable(n_evicted - 1);
+        let threshold = *threshold;
+        self.buckets.retain(|_, bucket| bucket.updated_at > threshold);
+    }
+}
+
+/*
+    the address a bucket is kept for
+
+    an IPv6 client usually gets a whole /64 from its provider and can
+    pick a new address in it for every request: the prefix is what
+    identifies it
+ */
+fn client_key(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V4(_) => client,
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(address) => IpAddr::V4(address),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(address) & !(u64::MAX as u128)))
+        }
+    }
+}
+
+/*
+    in-memory token buckets, one per route and client address,
+    or per /64 network for IPv6 clients
+
+    every replica keeps its own buckets: behind a load balancer
+    a client gets the configured limit on each of them
+ */
+pub struct RateLimiter {
+    routes: Vec<RouteRateLimit>,
+    trusted_proxies: Vec<TrustedProxy>,
+    buckets: Mutex<Buckets>,
+    max_buckets: usize
+}
+
+impl RateLimiter {
+    pub fn new(routes: Vec<RouteRateLimit>, trusted_proxies: Vec<TrustedProxy>) -> Result<Self, String> {
+        for route in &routes {
+            if route.capacity == 0 || route.refill_per_minute == 0 {
+                return Err(format!(
+                    "the rate limit of {} must have a positive capacity and refill rate",
+                    route.path
+                ));
+            }
+        }
+
+        Ok(Self {
+            routes,
+            trusted_proxies,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                pruned_at: Instant::now()
+            }),
+            max_buckets: MAX_BUCKETS
+        })
+    }
+
+    fn route_index(&self, method: &str, path: &str) -> Option<usize> {
+        self.routes.iter().position(|r| {
+            r.path == path && r.method.as_deref().is_none_or(|m| m.eq_ignore_ascii_case(method))
+        })
+    }
+
+    /*
+        take a token from the bucket of the client,
+        or tell how long it has to wait for the next one
+
+        once `max_buckets` clients are being throttled, the buckets
+        refilled the longest ago make room for the new ones
+     */
+    fn acquire(&self, route_index: usize, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let route = &self.routes[route_index];
+        let capacity = route.capacity as f64;
+        let tokens_per_second = route.refill_per_minute as f64 / 60.0;
+
+        let key = (route_index, client_key(client));
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.duration_since(buckets.pruned_at) >= PRUNE_INTERVAL {
+            buckets.prune(&self.routes, now);
+        }
+        if buckets.buckets.len() >= self.max_buckets && !buckets.buckets.contains_key(&key) {
+            buckets.prune(&self.routes, now);
+            if buckets.buckets.len() >= self.max_buckets {
+                buckets.evict_least_recently_refilled();
+            }
+        }
+
+        let bucket = buckets
+            .buckets
+            .entry(key)
+            .or_insert(Bucket { tokens: capacity, updated_at: now });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * tokens_per_second).min(capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / tokens_per_second))
+        }
+    }
+
+    /*
+        address of the client that issued the request
+
+        `X-Forwarded-For` is only believed when the request comes from a
+        trusted proxy, and is read from the right: the first address that
+        is not one of our proxies is the client, anything on its left
+        could have been made up by the client itself
+     */
+    fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut client = peer?;
+        if !self.is_trusted(client) {
+            return Some(client);
+        }
+
+        let forwarded_for = headers
+            .get_all("X-Forwarded-For")
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect::<Vec<_>>();
+        for hop in forwarded_for.into_iter().rev() {
+            match hop.trim().parse() {
+                Ok(hop) => client = hop,
+                // the proxy that appended it vouches for nothing we can use
+                Err(_) => break
+            }
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+        Some(client)
+    }
+
+    fn is_trusted(&self, address: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|p| p.contains(address))
+    }
+}
+
+/*
+    middleware throttling the routes listed in the configuration,
+    a client over its limit gets a 429 telling it when to come back
+ */
+pub async fn rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .expect("the rate limiter must be registered as application data");
+
+    let route_index = limiter.route_index(req.method().as_str(), req.path());
+    let client = limiter.client_ip(req.peer_addr().map(|a| a.ip()), req.headers());
+    if let (Some(route_index), Some(client)) = (route_index, client) {
+        if let Err(retry_after) = limiter.acquire(route_index, client, Instant::now()) {
+            // whole seconds, rounded up so that the client does not come back too early
+            let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            tracing::warn!(client = %client, path = req.path(), "rate limit exceeded");
+
+            let mut response = ProblemDetails::new(StatusCode::TOO_MANY_REQUESTS)
+                .with_detail(format!("too many requests, retry in {} seconds.", retry_after))
+                .to_response();
+            response.headers_mut().insert(RETRY_AFTER, retry_after.into());
+            let e = anyhow::anyhow!("client {} exceeded the rate limit of {}", client, req.path());
+            return Err(InternalError::from_response(e, response).into());
+        }
+    }
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimiter, RouteRateLimit, TrustedProxy};
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    fn limiter(trusted_proxies: &[&str]) -> RateLimiter {
+        let route = RouteRateLimit {
+            path: "/subscriptions".into(),
+            method: Some("POST".into()),
+            capacity: 2,
+            refill_per_minute: 6
+        };
+        let trusted_proxies = trusted_proxies
+            .iter()
+            .map(|p| TrustedProxy::try_from(p.to_string()).unwrap())
+            .collect();
+        RateLimiter::new(vec![route], trusted_proxies).unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(HeaderName::from_static("x-forwarded-for"), HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn a_client_can_burst_up_to_the_capacity() {
+        let limiter = limiter(&[]);
+        let now = Instant::now();
+
+        assert!(limiter.acquire(0, ip("1.2.3.4"), now).is_ok());
+        assert!(limiter.acquire(0, ip("1.2.3.4"), now).is_ok());
+        assert_eq!(limiter.acquire(0, ip("1.2.3.4"), now), Err(Duration::from_secs(10)));
+        // other clients have their own bucket
+        assert!(limiter.acquire(0, ip("5.6.7.8"), now).is_ok());
+    }
+
+    #[test]
+    fn tokens_are_given_back_over_time() {
+        let limiter = limiter(&[]);
+        let now = Instant::now();
+        limiter.acquire(0, ip("1.2.3.4"), now).unwrap();
+        limiter.acquire(0, ip("1.2.3.4"), now).unwrap();
+
+        assert!(limiter.acquire(0, ip("1.2.3.4"), now + Duration::from_secs(9)).is_err());
+        assert!(limiter.acquire(0, ip("1.2.3.4"), now + Duration::from_secs(11)).is_ok());
+    }
+
+    #[test]
+    fn ipv6_clients_share_the_bucket_of_their_64_prefix() {
+        let limiter = limiter(&[]);
+        let now = Instant::now();
+
+        assert!(limiter.acquire(0, ip("2001:db8:1:2::1"), now).is_ok());
+        assert!(limiter.acquire(0, ip("2001:db8:1:2:ffff::42"), now).is_ok());
+        assert!(limiter.acquire(0, ip("2001:db8:1:2:abcd::7"), now).is_err());
+        // the neighbouring /64 is another client
+        assert!(limiter.acquire(0, ip("2001:db8:1:3::1"), now).is_ok());
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_are_ipv4_clients() {
+        let limiter = limiter(&[]);
+        let now = Instant::now();
+        limiter.acquire(0, ip("1.2.3.4"), now).unwrap();
+        limiter.acquire(0, ip("1.2.3.4"), now).unwrap();
+
+        assert!(limiter.acquire(0, ip("::ffff:1.2.3.4"), now).is_err());
+        assert!(limiter.acquire(0, ip("::ffff:1.2.3.5"), now).is_ok());
+    }
+
+    #[test]
+    fn new_clients_are_still_served_once_the_buckets_are_full() {
+        let mut limiter = limiter(&[]);
+        limiter.max_buckets = 100;
+        let now = Instant::now();
+        // a single client holding a whole /48 of /64s
+        for i in 0..100u128 {
+            let address = std::net::Ipv6Addr::from((0x2001_0db8_0001u128 << 80) | (i << 64));
+            limiter.acquire(0, IpAddr::V6(address), now + Duration::from_millis(i as u64)).unwrap();
+        }
+
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.acquire(0, ip("1.2.3.4"), later).is_ok());
+        assert!(limiter.acquire(0, ip("1.2.3.4"), later).is_ok());
+        // the newcomer is throttled like anybody else
+        assert!(limiter.acquire(0, ip("1.2.3.4"), later).is_err());
+        // only the buckets refilled the longest ago were forgotten
+        let buckets = &limiter.buckets.lock().unwrap().buckets;
+        assert!(buckets.len() <= 100);
+        assert!(!buckets.contains_key(&(0, ip("2001:db8:1::"))));
+        assert!(buckets.contains_key(&(0, ip("2001:db8:1:63::"))));
+    }
+
+    #[test]
+    fn only_the_configured_routes_are_limited() {
+        let limiter = limiter(&[]);
+
+        assert_eq!(limiter.route_index("POST", "/subscriptions"), Some(0));
+        assert_eq!(limiter.route_index("post", "/subscriptions"), Some(0));
+        assert_eq!(limiter.route_index("GET", "/subscriptions"), None);
+        assert_eq!(limiter.route_index("POST", "/newsletters"), None);
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let limiter = limiter(&["10.0.0.1"]);
+
+        let client = limiter.client_ip(Some(ip("1.2.3.4")), &forwarded_for("5.6.7.8"));
+
+        assert_eq!(client, Some(ip("1.2.3.4")));
+    }
+
+    #[test]
+    fn forwarded_for_is_read_from_the_right_up_to_the_first_untrusted_hop() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+
+        // the client made up the first address
+        let client = limiter.client_ip(
+            Some(ip("10.0.0.1")),
+            &forwarded_for("6.6.6.6, 1.2.3.4, 10.1.2.3")
+        );
+
+        assert_eq!(client, Some(ip("1.2.3.4")));
+    }
+
+    #[test]
+    fn garbage_in_forwarded_for_stops_the_walk() {
+        let limiter = limiter(&["10.0.0.1"]);
+
+        let client = limiter.client_ip(Some(ip("10.0.0.1")), &forwarded_for("1.2.3.4, unknown"));
+
+        assert_eq!(client, Some(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn trusted_proxies_accept_addresses_and_cidr_ranges() {
+        let proxy = TrustedProxy::try_from("192.168.0.0/16".to_string()).unwrap();
+        assert!(proxy.contains(ip("192.168.4.2")));
+        assert!(!proxy.contains(ip("192.169.0.1")));
+        assert!(!proxy.contains(ip("::1")));
+
+        let proxy = TrustedProxy::try_from("::1".to_string()).unwrap();
+        assert!(proxy.contains(ip("::1")));
+
+        let proxy = TrustedProxy::try_from("0.0.0.0/0".to_string()).unwrap();
+        assert!(proxy.contains(ip("8.8.8.8")));
+
+        assert!(TrustedProxy::try_from("10.0.0.0/33".to_string()).is_err());
+        assert!(TrustedProxy::try_from("localhost".to_string()).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{ListName, ListNameError, ListSlug, ListSlugError};
+use crate::mailing_list::{get_list_by_slug, MailingList};
+use crate::problem_details::{InvalidParam, ProblemDetails};
+use crate::utils::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct NewListData {
+    slug: String,
+    name: String
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListUpdateData {
+    name: String
+}
+
+#[derive(thiserror::Error)]
+pub enum ListsError {
+    #[error("the list is invalid.")]
+    ValidationError(Vec<InvalidParam>),
+    #[error("there is already a list named '{0}'.")]
+    Conflict(String),
+    #[error("the list does not exist.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error)
+}
+
+impl std::fmt::Debug for ListsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ListsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ListsError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ListsError::Conflict(_) => StatusCode::CONFLICT,
+            ListsError::NotFound => StatusCode::NOT_FOUND,
+            ListsError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ListsError::ValidationError(invalid_params) => {
+                let mut problem = ProblemDetails::new(self.status_code()).with_detail(self.to_string());
+                for invalid_param in invalid_params {
+                    problem = problem.with_invalid_param(invalid_param.clone());
+                }
+                problem.to_response()
+            }
+            ListsError::Conflict(_) | ListsError::NotFound => ProblemDetails::new(self.status_code())
+                .with_detail(self.to_string())
+                .to_response(),
+            ListsError::UnexpectedError(_) => HttpResponse::new(self.status_code())
+        }
+    }
+}
+
+fn slug_invalid_param(e: ListSlugError) -> InvalidParam {
+    let code = match e {
+        ListSlugError::Empty => "empty",
+        ListSlugError::TooLong { .. } => "too_long",
+        ListSlugError::InvalidCharacter => "invalid_character",
+        ListSlugError::LeadingOrTrailingDash => "leading_or_trailing_dash"
+    };
+    InvalidParam { name: "slug", code, reason: e.to_string() }
+}
+
+fn name_invalid_param(e: ListNameError) -> InvalidParam {
+    let code = match e {
+        ListNameError::Empty => "empty",
+        ListNameError::TooLong { .. } => "too_long"
+    };
+    InvalidParam { name: "name", code, reason: e.to_string() }
+}
+
+#[derive(serde::Serialize)]
+pub struct ListSummary {
+    slug: String,
+    name: String,
+    created_at: DateTime<Utc>,
+    members: MemberCounts
+}
+
+#[derive(serde::Serialize)]
+pub struct MemberCounts {
+    pending_confirmation: i64,
+    confirmed: i64,
+    unsubscribed: i64
+}
+
+#[tracing::instrument(name = "List mailing lists", skip(pool))]
+pub async fn list_lists(pool: web::Data<PgPool>) -> Result<HttpResponse, ListsError> {
+    let lists = sqlx::query!(
+        r#"
+        SELECT
+            l.slug,
+            l.name,
+            l.created_at,
+            count(m.subscriber_id) FILTER (WHERE m.status = 'pending_confirmation') as "pending_confirmation!",
+            count(m.subscriber_id) FILTER (WHERE m.status = 'confirmed') as "confirmed!",
+            count(m.subscriber_id) FILTER (WHERE m.status = 'unsubscribed') as "unsubscribed!"
+        FROM lists l
+        LEFT JOIN list_memberships m ON m.list_id = l.list_id
+        GROUP BY l.list_id
+        ORDER BY l.created_at, l.slug
+        "#
+        )
+        .fetch_all(pool.get_ref())
+        .await
+        .context("failed to fetch the mailing lists")?;
+
+    let lists: Vec<_> = lists
+        .into_iter()
+        .map(|l| ListSummary {
+            slug: l.slug,
+            name: l.name,
+            created_at: l.created_at,
+            members: MemberCounts {
+                pending_confirmation: l.pending_confirmation,
+                confirmed: l.confirmed,
+                unsubscribed: l.unsubscribed
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "lists": lists })))
+}
+
+/*
+    create a new, empty, list
+
+    the slug ends up in signup forms and links, it cannot
+    be changed afterwards: only the name can
+ */
+#[tracing::instrument(name = "Create a mailing list", skip(body, pool), fields(slug = %body.slug))]
+pub async fn create_list(
+    body: web::Json<NewListData>,
+    pool: web::Data<PgPool>
+) -> Result<HttpResponse, ListsError> {
+    let body = body.into_inner();
+    let slug = ListSlug::parse(body.slug).map_err(slug_invalid_param);
+    let name = ListName::parse(body.name).map_err(name_invalid_param);
+    let (slug, name) = match (slug, name) {
+        (Ok(slug), Ok(name)) => (slug, name),
+        (slug, name) => {
+            let invalid_params = slug.err().into_iter().chain(name.err()).collect();
+            return Err(ListsError::ValidationError(invalid_params));
+        }
+    };
+
+    let list = MailingList {
+        list_id: Uuid::new_v4(),
+        slug: slug.as_ref().to_owned(),
+        name: name.as_ref().to_owned(),
+        created_at: Utc::now()
+    };
+    // the unique constraint settles concurrent creations for us
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        list.list_id,
+        list.slug,
+        list.name,
+        list.created_at
+        )
+        .execute(pool.get_ref())
+        .await
+        .context("failed to store the mailing list")?
+        .rows_affected();
+    if n_inserted_rows == 0 {
+        return Err(ListsError::Conflict(list.slug));
+    }
+
+    Ok(HttpResponse::Created().json(list))
+}
+
+#[tracing::instrument(name = "Rename a mailing list", skip(body, pool))]
+pub async fn update_list(
+    slug: web::Path<String>,
+    body: web::Json<ListUpdateData>,
+    pool: web::Data<PgPool>
+) -> Result<HttpResponse, ListsError> {
+    let name = ListName::parse(body.into_inner().name)
+        .map_err(|e| ListsError::ValidationError(vec![name_invalid_param(e)]))?;
+
+    let n_updated_rows = sqlx::query!(
+        r#"UPDATE lists SET name = $2 WHERE slug = $1"#,
+        slug.as_str(),
+        name.as_ref()
+        )
+        .execute(pool.get_ref())
+        .await
+        .context("failed to rename the mailing list")?
+        .rows_affected();
+    if n_updated_rows == 0 {
+        return Err(ListsError::NotFound);
+    }
+
+    let list = get_list_by_slug(pool.get_ref(), Some(slug.as_str()))
+        .await
+        .context("failed to fetch the mailing list")?
+        .ok_or(ListsError::NotFound)?;
+    Ok(HttpResponse::Ok().json(list))
+}
@@ -0,0 +1,12 @@
+use actix_web::HttpResponse;
+
+use crate::session_state::TypedSession;
+use crate::utils::see_other;
+
+/*
+    drop the server-side session, the browser is sent back to the login form
+ */
+pub async fn log_out(session: TypedSession) -> HttpResponse {
+    session.log_out();
+    see_other("/login")
+}
@@ -0,0 +1,17 @@
+mod dashboard;
+mod lists;
+mod logout;
+mod newsletter_preview;
+mod password;
+mod scheduled_issues;
+mod subscribers;
+mod subscribers_import;
+
+pub use dashboard::admin_dashboard;
+pub use lists::{create_list, list_lists, update_list};
+pub use logout::log_out;
+pub use newsletter_preview::preview_newsletter;
+pub use password::{change_password, change_password_form};
+pub use scheduled_issues::{cancel_issue, list_scheduled_issues, reschedule_issue};
+pub use subscribers::{export_subscribers, get_subscriber, list_subscribers};
+pub use subscribers_import::import_subscribers;
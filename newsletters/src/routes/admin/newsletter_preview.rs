@@ -0,0 +1,29 @@
+use actix_web::{web, HttpResponse};
+
+use crate::issue_rendering::IssueRenderer;
+
+#[derive(serde::Deserialize)]
+pub struct PreviewData {
+    title: String,
+    content: MarkdownContent
+}
+
+#[derive(serde::Deserialize)]
+pub struct MarkdownContent {
+    markdown: String
+}
+
+/*
+    both renderings of an issue written in Markdown, exactly as they
+    would be stored by `publish_newsletter`: nothing is saved or sent
+
+    the unsubscribe link is not part of them, it is added to
+    every copy at delivery time
+ */
+#[tracing::instrument(name = "Preview a newsletter issue", skip(body, renderer), fields(title = %body.title))]
+pub async fn preview_newsletter(
+    body: web::Json<PreviewData>,
+    renderer: web::Data<IssueRenderer>
+) -> HttpResponse {
+    HttpResponse::Ok().json(renderer.render(&body.title, &body.content.markdown))
+}
@@ -0,0 +1,69 @@
+use actix_web::cookie::Cookie;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+use super::{error_message, PASSWORD_ERROR_COOKIE};
+use crate::authentication::{must_change_password, UserId};
+use crate::utils::e500;
+
+/*
+    form to change the password of the logged-in user, the only page
+    they can reach while they still have the password they were given
+ */
+pub async fn change_password_form(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>
+) -> Result<HttpResponse, actix_web::Error> {
+    let notice_html = if must_change_password(**user_id, &pool).await.map_err(e500)? == Some(true) {
+        "<p><i>You must choose a new password before going any further.</i></p>"
+    } else {
+        ""
+    };
+    let error_html = request
+        .cookie(PASSWORD_ERROR_COOKIE)
+        .and_then(|cookie| error_message(cookie.value()))
+        .map(|message| format!("<p><i>{}</i></p>", message))
+        .unwrap_or_default();
+
+    let mut response = HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Change Password</title>
+</head>
+<body>
+    {notice_html}
+    {error_html}
+    <form action="/admin/password" method="post">
+        <label>Current password
+            <input type="password" placeholder="Enter current password" name="current_password">
+        </label>
+        <br>
+        <label>New password
+            <input type="password" placeholder="Enter new password" name="new_password">
+        </label>
+        <br>
+        <label>Confirm new password
+            <input type="password" placeholder="Type the new password again" name="new_password_check">
+        </label>
+        <br>
+        <button type="submit">Change password</button>
+    </form>
+    <form name="logoutForm" action="/admin/logout" method="post">
+        <input type="submit" value="Logout">
+    </form>
+</body>
+</html>"#
+        ));
+
+    // the error is only displayed once
+    response
+        .add_removal_cookie(&Cookie::new(PASSWORD_ERROR_COOKIE, ""))
+        .expect("the removal cookie is a valid header value");
+    Ok(response)
+}
@@ -0,0 +1,19 @@
+mod get;
+mod post;
+
+pub use get::change_password_form;
+pub use post::change_password;
+
+// cookie carrying the reason the last change was refused, as one of the
+// codes below: the form never echoes back anything else
+const PASSWORD_ERROR_COOKIE: &str = "password_error";
+
+fn error_message(code: &str) -> Option<&'static str> {
+    match code {
+        "invalid_current_password" => Some("The current password is incorrect."),
+        "mismatch" => Some("You entered two different new passwords, the field values must match."),
+        "invalid_length" => Some("The new password must be between 12 and 128 characters long."),
+        "unchanged" => Some("The new password must be different from the current one."),
+        _ => None
+    }
+}
@@ -0,0 +1,68 @@
+use actix_web::cookie::Cookie;
+use actix_web::{web, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use super::PASSWORD_ERROR_COOKIE;
+use crate::authentication::{self, validate_credentials, AuthError, Credentials, UserId, PASSWORD_LENGTH};
+use crate::routes::admin::dashboard::get_username;
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>
+}
+
+/*
+    replace the password of the logged-in user, who has to prove
+    they know the current one: a session left open is not enough
+ */
+#[tracing::instrument(name = "Change password", skip(form, pool), fields(user_id = %*user_id))]
+pub async fn change_password(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let new_password = form.0.new_password;
+
+    if new_password.expose_secret() != form.0.new_password_check.expose_secret() {
+        return Ok(password_redirect("mismatch"));
+    }
+    if !PASSWORD_LENGTH.contains(&new_password.expose_secret().chars().count()) {
+        return Ok(password_redirect("invalid_length"));
+    }
+    if new_password.expose_secret() == form.0.current_password.expose_secret() {
+        return Ok(password_redirect("unchanged"));
+    }
+
+    let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password
+    };
+    if let Err(e) = validate_credentials(credentials, &pool).await {
+        return match e {
+            AuthError::InvalidCredentials(_) => Ok(password_redirect("invalid_current_password")),
+            AuthError::UnexpectedError(_) => Err(e500(e))
+        };
+    }
+
+    authentication::change_password(*user_id, new_password, &pool)
+        .await
+        .map_err(e500)?;
+    Ok(see_other("/admin/dashboard"))
+}
+
+/*
+    send the user back to the form, flagging why the change was refused
+ */
+fn password_redirect(code: &str) -> HttpResponse {
+    let mut response = see_other("/admin/password");
+    response
+        .add_cookie(&Cookie::new(PASSWORD_ERROR_COOKIE, code))
+        .expect("the password error cookie is a valid header value");
+    response
+}
@@ -0,0 +1,199 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::problem_details::{InvalidParam, ProblemDetails};
+use crate::utils::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleData {
+    scheduled_at: DateTime<Utc>
+}
+
+#[derive(thiserror::Error)]
+pub enum ScheduleError {
+    #[error("the schedule is invalid.")]
+    ValidationError(Vec<InvalidParam>),
+    #[error("the issue is {0}, it can no longer be changed.")]
+    Conflict(String),
+    #[error("the issue does not exist.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error)
+}
+
+impl std::fmt::Debug for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ScheduleError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ScheduleError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ScheduleError::Conflict(_) => StatusCode::CONFLICT,
+            ScheduleError::NotFound => StatusCode::NOT_FOUND,
+            ScheduleError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ScheduleError::ValidationError(invalid_params) => {
+                let mut problem = ProblemDetails::new(self.status_code()).with_detail(self.to_string());
+                for invalid_param in invalid_params {
+                    problem = problem.with_invalid_param(invalid_param.clone());
+                }
+                problem.to_response()
+            }
+            ScheduleError::Conflict(_) | ScheduleError::NotFound => ProblemDetails::new(self.status_code())
+                .with_detail(self.to_string())
+                .to_response(),
+            ScheduleError::UnexpectedError(_) => HttpResponse::new(self.status_code())
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct ScheduledIssue {
+    issue_id: Uuid,
+    title: String,
+    list: String,
+    status: String,
+    scheduled_at: Option<DateTime<Utc>>
+}
+
+/*
+    issues waiting for the scheduler, the next one to go out first
+ */
+#[tracing::instrument(name = "List scheduled issues", skip(pool))]
+pub async fn list_scheduled_issues(pool: web::Data<PgPool>) -> Result<HttpResponse, ScheduleError> {
+    let issues = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT
+            i.newsletter_issue_id as issue_id,
+            i.title,
+            l.slug as list,
+            i.status,
+            i.scheduled_at
+        FROM newsletter_issues i
+        JOIN lists l ON l.list_id = i.list_id
+        WHERE i.status = 'scheduled'
+        ORDER BY i.scheduled_at, i.newsletter_issue_id
+        "#
+        )
+        .fetch_all(pool.get_ref())
+        .await
+        .context("failed to fetch the scheduled issues")?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "issues": issues })))
+}
+
+/*
+    move a scheduled issue to another time
+
+    like cancellations, it only applies to issues that are still
+    'scheduled': once the scheduler has queued the deliveries
+    the issue is already on its way
+ */
+#[tracing::instrument(name = "Reschedule an issue", skip(body, pool))]
+pub async fn reschedule_issue(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<RescheduleData>,
+    pool: web::Data<PgPool>
+) -> Result<HttpResponse, ScheduleError> {
+    let issue_id = issue_id.into_inner();
+    if body.scheduled_at <= Utc::now() {
+        return Err(ScheduleError::ValidationError(vec![InvalidParam {
+            name: "scheduled_at",
+            code: "in_the_past",
+            reason: "the issue must be scheduled in the future.".into()
+        }]));
+    }
+
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_at = $2
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'scheduled'
+        "#,
+        issue_id,
+        body.scheduled_at
+        )
+        .execute(pool.get_ref())
+        .await
+        .context("failed to reschedule the issue")?
+        .rows_affected();
+    if n_updated_rows == 0 {
+        return Err(unchangeable_issue(&pool, issue_id).await);
+    }
+
+    Ok(HttpResponse::Ok().json(get_issue(&pool, issue_id).await?))
+}
+
+#[tracing::instrument(name = "Cancel a scheduled issue", skip(pool))]
+pub async fn cancel_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>
+) -> Result<HttpResponse, ScheduleError> {
+    let issue_id = issue_id.into_inner();
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'cancelled'
+        WHERE
+            newsletter_issue_id = $1 AND
+            status = 'scheduled'
+        "#,
+        issue_id
+        )
+        .execute(pool.get_ref())
+        .await
+        .context("failed to cancel the issue")?
+        .rows_affected();
+    if n_updated_rows == 0 {
+        return Err(unchangeable_issue(&pool, issue_id).await);
+    }
+
+    Ok(HttpResponse::Ok().json(get_issue(&pool, issue_id).await?))
+}
+
+// why an update matching only scheduled issues did not find this one
+async fn unchangeable_issue(pool: &PgPool, issue_id: Uuid) -> ScheduleError {
+    match get_issue(pool, issue_id).await {
+        Ok(issue) => ScheduleError::Conflict(issue.status),
+        Err(e) => e
+    }
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<ScheduledIssue, ScheduleError> {
+    let issue = sqlx::query_as!(
+        ScheduledIssue,
+        r#"
+        SELECT
+            i.newsletter_issue_id as issue_id,
+            i.title,
+            l.slug as list,
+            i.status,
+            i.scheduled_at
+        FROM newsletter_issues i
+        JOIN lists l ON l.list_id = i.list_id
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        issue_id
+        )
+        .fetch_optional(pool)
+        .await
+        .context("failed to fetch the issue")?
+        .ok_or(ScheduleError::NotFound)?;
+
+    Ok(issue)
+}
@@ -0,0 +1,347 @@
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::problem_details::{InvalidParam, ProblemDetails};
+use crate::subscriber_export::subscribers_csv;
+use crate::subscriber_filter::{FilterParameters, SubscriberFilter, SubscriberSummary};
+use crate::utils::error_chain_fmt;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize)]
+pub struct ListParameters {
+    #[serde(flatten)]
+    filter: FilterParameters,
+    limit: Option<String>,
+    cursor: Option<String>
+}
+
+#[derive(thiserror::Error)]
+pub enum SubscribersError {
+    #[error("the query parameters are invalid.")]
+    ValidationError(Vec<InvalidParam>),
+    #[error("the subscriber does not exist.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error)
+}
+
+impl std::fmt::Debug for SubscribersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscribersError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscribersError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribersError::NotFound => StatusCode::NOT_FOUND,
+            SubscribersError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribersError::ValidationError(invalid_params) => {
+                let mut problem = ProblemDetails::new(self.status_code()).with_detail(self.to_string());
+                for invalid_param in invalid_params {
+                    problem = problem.with_invalid_param(invalid_param.clone());
+                }
+                problem.to_response()
+            }
+            SubscribersError::NotFound => ProblemDetails::new(self.status_code())
+                .with_detail(self.to_string())
+                .to_response(),
+            SubscribersError::UnexpectedError(_) => HttpResponse::new(self.status_code())
+        }
+    }
+}
+
+/// Position of the last subscriber of a page, in `(subscribed_at, id)` order.
+#[derive(Debug, PartialEq, Eq)]
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid
+}
+
+impl Cursor {
+    // opaque to clients, they are only expected to hand it back
+    fn encode(&self) -> String {
+        let raw = format!(
+            "{}|{}",
+            self.subscribed_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.id
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    fn decode(s: &str) -> Option<Cursor> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(s).ok()?).ok()?;
+        let (subscribed_at, id) = raw.split_once('|')?;
+        Some(Cursor {
+            subscribed_at: DateTime::parse_from_rfc3339(subscribed_at).ok()?.with_timezone(&Utc),
+            id: Uuid::try_parse(id).ok()?
+        })
+    }
+}
+
+struct Page {
+    filter: SubscriberFilter,
+    limit: i64,
+    cursor: Option<Cursor>
+}
+
+impl TryFrom<ListParameters> for Page {
+    type Error = SubscribersError;
+
+    fn try_from(value: ListParameters) -> Result<Self, Self::Error> {
+        let (filter, mut invalid_params) = match SubscriberFilter::try_from(value.filter) {
+            Ok(filter) => (Some(filter), Vec::new()),
+            Err(invalid_params) => (None, invalid_params)
+        };
+
+        let mut limit = DEFAULT_PAGE_SIZE;
+        if let Some(value) = value.limit {
+            match value.parse::<i64>() {
+                Ok(value) if (1..=MAX_PAGE_SIZE).contains(&value) => limit = value,
+                _ => invalid_params.push(InvalidParam {
+                    name: "limit",
+                    code: "out_of_range",
+                    reason: format!("the limit must be between 1 and {}.", MAX_PAGE_SIZE)
+                })
+            }
+        }
+        let mut cursor = None;
+        if let Some(value) = value.cursor {
+            match Cursor::decode(&value) {
+                Some(value) => cursor = Some(value),
+                None => invalid_params.push(InvalidParam {
+                    name: "cursor",
+                    code: "invalid_cursor",
+                    reason: "the cursor was not returned by a previous page.".into()
+                })
+            }
+        }
+
+        match filter {
+            Some(filter) if invalid_params.is_empty() => Ok(Page { filter, limit, cursor }),
+            _ => Err(SubscribersError::ValidationError(invalid_params))
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberPage {
+    subscribers: Vec<SubscriberSummary>,
+    // `None` on the last page
+    next_cursor: Option<String>
+}
+
+/*
+    list the subscribers, oldest first
+
+    pages are delimited with a cursor on `(subscribed_at, id)` rather
+    than an offset: the database seeks straight to the first row of the
+    page, however deep into the list it is
+ */
+#[tracing::instrument(name = "List subscribers", skip(parameters, pool))]
+pub async fn list_subscribers(
+    parameters: web::Query<ListParameters>,
+    pool: web::Data<PgPool>
+) -> Result<HttpResponse, SubscribersError> {
+    let page = Page::try_from(parameters.into_inner())?;
+
+    let mut subscribers = get_subscribers(&pool, &page)
+        .await
+        .context("failed to fetch subscribers")?;
+
+    // one row more than requested tells us whether there is a next page
+    let next_cursor = if subscribers.len() as i64 > page.limit {
+        subscribers.truncate(page.limit as usize);
+        subscribers.last().map(|s| {
+            Cursor {
+                subscribed_at: s.subscribed_at,
+                id: s.id
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(SubscriberPage {
+        subscribers,
+        next_cursor
+    }))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_subscribers(
+    pool: &PgPool,
+    page: &Page
+) -> Result<Vec<SubscriberSummary>, sqlx::Error> {
+    let mut query = page.filter.select_subscribers();
+    // the columns of `list_memberships_subscribed_at_idx`: the same
+    // values taken from `subscriptions` could not be used to seek it
+    if let Some(cursor) = &page.cursor {
+        query
+            .push(" AND (m.subscribed_at, m.subscriber_id) > (")
+            .push_bind(cursor.subscribed_at)
+            .push(", ")
+            .push_bind(cursor.id)
+            .push(")");
+    }
+    query
+        .push(" ORDER BY m.subscribed_at, m.subscriber_id LIMIT ")
+        .push_bind(page.limit + 1);
+
+    query
+        .build_query_as::<SubscriberSummary>()
+        .fetch_all(pool)
+        .await
+}
+
+/*
+    CSV export of the subscribers matching the same filters as the listing,
+    streamed to the client as rows come out of the database
+
+    once the first chunk is out the status code cannot change anymore:
+    a failure halfway through aborts the connection instead
+ */
+#[tracing::instrument(name = "Export subscribers", skip(parameters, pool))]
+pub async fn export_subscribers(
+    parameters: web::Query<FilterParameters>,
+    pool: web::Data<PgPool>
+) -> Result<HttpResponse, SubscribersError> {
+    let filter = SubscriberFilter::try_from(parameters.into_inner())
+        .map_err(SubscribersError::ValidationError)?;
+
+    let csv = subscribers_csv(pool.get_ref().clone(), filter).inspect_err(|e| {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "the subscriber export failed"
+        )
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("subscribers.csv".into())]
+        })
+        .streaming(csv))
+}
+
+#[derive(serde::Serialize)]
+pub struct SubscriberDetails {
+    id: Uuid,
+    email: String,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+    lists: Vec<Membership>,
+    deliveries: DeliveryCounts
+}
+
+#[derive(serde::Serialize)]
+pub struct Membership {
+    list: String,
+    status: String,
+    subscribed_at: DateTime<Utc>
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryCounts {
+    delivered: i64,
+    failed: i64,
+    skipped: i64
+}
+
+#[tracing::instrument(name = "Get subscriber details", skip(pool))]
+pub async fn get_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>
+) -> Result<HttpResponse, SubscribersError> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT
+            s.id,
+            s.email,
+            s.name,
+            s.subscribed_at,
+            count(*) FILTER (WHERE o.outcome = 'delivered') as "delivered!",
+            count(*) FILTER (WHERE o.outcome = 'failed') as "failed!",
+            count(*) FILTER (WHERE o.outcome = 'skipped') as "skipped!"
+        FROM subscriptions s
+        LEFT JOIN issue_delivery_outcomes o ON o.subscriber_email = s.email
+        WHERE s.id = $1
+        GROUP BY s.id
+        "#,
+        subscriber_id
+        )
+        .fetch_optional(pool.get_ref())
+        .await
+        .context("failed to fetch the subscriber")?
+        .ok_or(SubscribersError::NotFound)?;
+    let lists = sqlx::query_as!(
+        Membership,
+        r#"
+        SELECT l.slug as list, m.status, m.subscribed_at
+        FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id
+        WHERE m.subscriber_id = $1
+        ORDER BY m.subscribed_at
+        "#,
+        subscriber_id
+        )
+        .fetch_all(pool.get_ref())
+        .await
+        .context("failed to fetch the list memberships")?;
+
+    Ok(HttpResponse::Ok().json(SubscriberDetails {
+        id: subscriber.id,
+        email: subscriber.email,
+        name: subscriber.name,
+        subscribed_at: subscriber.subscribed_at,
+        lists,
+        deliveries: DeliveryCounts {
+            delivered: subscriber.delivered,
+            failed: subscriber.failed,
+            skipped: subscriber.skipped
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cursor;
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    #[test]
+    fn a_cursor_survives_a_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc.timestamp_opt(1_684_567_890, 123_456_000).unwrap(),
+            id: Uuid::new_v4()
+        };
+
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn garbage_is_not_a_cursor() {
+        assert_eq!(Cursor::decode("definitely-not-a-cursor"), None);
+        assert_eq!(Cursor::decode(""), None);
+    }
+}
@@ -0,0 +1,119 @@
+use actix_web::http::StatusCode;
+use actix_web::{mime, web, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::domain_policy::DomainPolicy;
+use crate::mailing_list::get_list_by_slug;
+use crate::problem_details::{InvalidParam, ProblemDetails};
+use crate::subscriber_import::{import_subscribers as import, parse_csv, parse_json, ImportStatus};
+use crate::utils::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct ImportParameters {
+    status: Option<String>,
+    // slug of the list, the default one if omitted
+    list: Option<String>
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    #[error("unsupported content type '{0}', use text/csv or application/json.")]
+    UnsupportedMediaType(String),
+    #[error("the status must be either confirmed or pending_confirmation.")]
+    InvalidStatus,
+    #[error("there is no list named '{0}'.")]
+    UnknownList(String),
+    #[error("{0}")]
+    InvalidFile(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error)
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ImportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ImportError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ImportError::InvalidStatus | ImportError::UnknownList(_) | ImportError::InvalidFile(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            ImportError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ImportError::InvalidStatus => ProblemDetails::new(self.status_code())
+                .with_invalid_param(InvalidParam {
+                    name: "status",
+                    code: "unknown_status",
+                    reason: self.to_string()
+                })
+                .to_response(),
+            ImportError::UnknownList(_) => ProblemDetails::new(self.status_code())
+                .with_invalid_param(InvalidParam {
+                    name: "list",
+                    code: "unknown_list",
+                    reason: self.to_string()
+                })
+                .to_response(),
+            ImportError::UnsupportedMediaType(_) | ImportError::InvalidFile(_) => {
+                ProblemDetails::new(self.status_code())
+                    .with_detail(self.to_string())
+                    .to_response()
+            }
+            ImportError::UnexpectedError(_) => HttpResponse::new(self.status_code())
+        }
+    }
+}
+
+/*
+    bulk import of subscribers from a CSV file or a JSON array into a list,
+    the `status` query parameter tells whether they already confirmed,
+    the others get a confirmation email from the delivery worker
+
+    rows failing validation, or the domain policy of the signup form,
+    are skipped and listed in the report, they do not prevent the
+    valid rows from being imported
+ */
+#[tracing::instrument(name = "Import subscribers", skip(request, body, pool, parameters, domain_policy))]
+pub async fn import_subscribers(
+    request: HttpRequest,
+    body: web::Bytes,
+    parameters: web::Query<ImportParameters>,
+    pool: web::Data<PgPool>,
+    domain_policy: web::Data<DomainPolicy>
+) -> Result<HttpResponse, ImportError> {
+    let status = match parameters.status.as_deref() {
+        Some("confirmed") => ImportStatus::Confirmed,
+        Some("pending_confirmation") => ImportStatus::PendingConfirmation,
+        _ => return Err(ImportError::InvalidStatus)
+    };
+    let list = get_list_by_slug(pool.get_ref(), parameters.list.as_deref())
+        .await
+        .context("failed to fetch the mailing list")?
+        .ok_or_else(|| ImportError::UnknownList(parameters.list.clone().unwrap_or_default()))?;
+
+    let mime_type = request.mime_type().ok().flatten();
+    let rows = match mime_type.as_ref().map(|m| (m.type_(), m.subtype())) {
+        Some((mime::TEXT, mime::CSV)) => parse_csv(&body),
+        Some((mime::APPLICATION, mime::JSON)) => parse_json(&body),
+        _ => return Err(ImportError::UnsupportedMediaType(request.content_type().to_owned()))
+    }
+    .map_err(ImportError::InvalidFile)?;
+
+    let report = import(&pool, rows, list.list_id, status, &domain_policy).await?;
+    tracing::info!(
+        imported = report.imported,
+        rejected = report.rejected.len(),
+        "subscribers imported"
+    );
+
+    Ok(HttpResponse::Ok().json(report))
+}
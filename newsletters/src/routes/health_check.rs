@@ -1,4 +1,28 @@
-use actix_web::{HttpRequest, Responder, HttpResponse};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, Responder, HttpResponse};
+use sqlx::migrate::Migrator;
+use sqlx::PgPool;
+
+use crate::email_client::EmailClient;
+use crate::worker_heartbeat::{last_heartbeat_age, WorkerKind, MAX_HEARTBEAT_AGE};
+
+// a component slower than that to answer is as good as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// the email provider is asked at most that often, whatever the number of probes
+const EMAIL_CHECK_TTL: Duration = Duration::from_secs(30);
+// a bit less than `CHECK_TIMEOUT`, so that a provider too slow to answer
+// is remembered rather than cut short by the timeout of the probe
+const EMAIL_CHECK_TIMEOUT: Duration = Duration::from_millis(1800);
+
+// the migrations this binary was built with
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
 /*
     handler to check if our web server still healthy or not
@@ -6,4 +30,224 @@ use actix_web::{HttpRequest, Responder, HttpResponse};
  */
 pub async fn health_check(_req: HttpRequest) -> impl Responder {
     HttpResponse::Ok().finish()
-}
\ No newline at end of file
+}
+
+/*
+    liveness probe: the process is up and serving requests
+
+    it never looks at our dependencies, a database outage must not
+    get every replica restarted
+ */
+pub async fn liveness() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(serde_json::json!({ "status": "ok" }))
+}
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ComponentStatus {
+    Up,
+    Down
+}
+
+#[derive(serde::Serialize)]
+struct ComponentHealth {
+    status: ComponentStatus,
+    latency_ms: u64,
+    // why the component is down, never an error message: they end up in the logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>
+}
+
+/*
+    why a check failed
+
+    the probe is public, the errors of our dependencies may carry
+    hostnames or credentials: they are logged, only the fixed
+    message of `Unhealthy` goes into the response
+ */
+enum CheckError {
+    Unexpected(String),
+    Unhealthy(String)
+}
+
+impl From<sqlx::Error> for CheckError {
+    fn from(e: sqlx::Error) -> Self {
+        CheckError::Unexpected(e.to_string())
+    }
+}
+
+/*
+    last outcome of the email provider check, shared by the workers
+
+    opening a connection to the provider on every probe would be a
+    burden on it, and on our sending quota for some of them
+ */
+#[derive(Default)]
+pub struct EmailCheckCache(Mutex<Option<(Instant, Result<(), String>)>>);
+
+impl EmailCheckCache {
+    async fn check(&self, email_client: &EmailClient) -> Result<(), CheckError> {
+        // held during the check, concurrent probes wait for its outcome
+        let mut cached = self.0.lock().await;
+        if let Some((checked_at, outcome)) = cached.as_ref() {
+            if checked_at.elapsed() < EMAIL_CHECK_TTL {
+                return outcome.clone().map_err(CheckError::Unhealthy);
+            }
+        }
+
+        let outcome = match tokio::time::timeout(EMAIL_CHECK_TIMEOUT, email_client.check_connection()).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => {
+                tracing::warn!(error.cause_chain = ?e, error.message = %e, "the email provider check failed");
+                Err("the email provider is unreachable".to_string())
+            }
+            Err(_) => Err(format!("no answer within {}ms", EMAIL_CHECK_TIMEOUT.as_millis()))
+        };
+        *cached = Some((Instant::now(), outcome.clone()));
+        outcome.map_err(CheckError::Unhealthy)
+    }
+}
+
+impl ComponentHealth {
+    fn is_up(&self) -> bool {
+        self.status == ComponentStatus::Up
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Components {
+    database: ComponentHealth,
+    migrations: ComponentHealth,
+    email: ComponentHealth,
+    delivery_worker: ComponentHealth,
+    issue_scheduler: ComponentHealth
+}
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Readiness {
+    // every component is up
+    Ok,
+    // requests can be served, but emails may be late
+    Degraded,
+    // requests would fail, traffic should go to other replicas
+    Unavailable
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessReport {
+    status: Readiness,
+    components: Components
+}
+
+/*
+    readiness probe: whether this replica should receive traffic
+
+    the checks run concurrently, each bounded by `CHECK_TIMEOUT`, the
+    outcome of the email check is reused for `EMAIL_CHECK_TTL`;
+    only the database and its schema are critical and answer 503
+    when down, an unreachable email provider or stopped background
+    loops are reported as 'degraded' with a 200: taking the API out
+    of rotation would not bring them back
+ */
+#[tracing::instrument(name = "Readiness probe", skip(pool, email_client, email_check))]
+pub async fn readiness(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    email_check: web::Data<EmailCheckCache>
+) -> HttpResponse {
+    let (database, migrations, email, delivery_worker, issue_scheduler) = tokio::join!(
+        check("database", check_database(&pool)),
+        check("migrations", check_migrations(&pool)),
+        check("email", email_check.check(&email_client)),
+        check("delivery_worker", check_heartbeat(&pool, WorkerKind::DeliveryWorker)),
+        check("issue_scheduler", check_heartbeat(&pool, WorkerKind::IssueScheduler))
+    );
+    let components = Components { database, migrations, email, delivery_worker, issue_scheduler };
+
+    let status = if !components.database.is_up() || !components.migrations.is_up() {
+        Readiness::Unavailable
+    } else if !components.email.is_up()
+        || !components.delivery_worker.is_up()
+        || !components.issue_scheduler.is_up()
+    {
+        Readiness::Degraded
+    } else {
+        Readiness::Ok
+    };
+    let report = ReadinessReport { status, components };
+    if status != Readiness::Ok {
+        tracing::warn!(
+            report = %serde_json::to_string(&report).unwrap_or_default(),
+            "the service is not fully ready"
+        );
+    }
+
+    let status_code = match status {
+        Readiness::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Readiness::Ok | Readiness::Degraded => StatusCode::OK
+    };
+    HttpResponse::build(status_code)
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(report)
+}
+
+async fn check(component: &str, probe: impl Future<Output = Result<(), CheckError>>) -> ComponentHealth {
+    let started_at = Instant::now();
+    let outcome = tokio::time::timeout(CHECK_TIMEOUT, probe)
+        .await
+        .unwrap_or_else(|_| {
+            Err(CheckError::Unhealthy(format!("no answer within {}ms", CHECK_TIMEOUT.as_millis())))
+        });
+    let latency_ms = started_at.elapsed().as_millis() as u64;
+
+    let detail = match outcome {
+        Ok(()) => None,
+        Err(CheckError::Unexpected(e)) => {
+            tracing::warn!(component, error.message = %e, "a readiness check failed");
+            Some("the check failed".to_string())
+        }
+        Err(CheckError::Unhealthy(reason)) => Some(reason)
+    };
+    ComponentHealth {
+        status: if detail.is_none() { ComponentStatus::Up } else { ComponentStatus::Down },
+        latency_ms,
+        detail
+    }
+}
+
+// the pool connects lazily, this is the first query to tell whether Postgres is there
+async fn check_database(pool: &PgPool) -> Result<(), CheckError> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
+// the migrations are applied before a new version is rolled out, never by the API
+async fn check_migrations(pool: &PgPool) -> Result<(), CheckError> {
+    let applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+        .fetch_all(pool)
+        .await?;
+
+    let pending: Vec<String> = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration() && !applied.contains(&m.version))
+        .map(|m| m.version.to_string())
+        .collect();
+    if !pending.is_empty() {
+        tracing::warn!(pending = %pending.join(", "), "some migrations are not applied");
+        return Err(CheckError::Unhealthy("some migrations are not applied".into()));
+    }
+    Ok(())
+}
+
+async fn check_heartbeat(pool: &PgPool, kind: WorkerKind) -> Result<(), CheckError> {
+    match last_heartbeat_age(pool, kind).await? {
+        None => Err(CheckError::Unhealthy("no heartbeat was ever recorded".into())),
+        Some(age) if age > MAX_HEARTBEAT_AGE => {
+            Err(CheckError::Unhealthy(format!("last heartbeat {}s ago", age.as_secs())))
+        }
+        Some(_) => Ok(())
+    }
+}
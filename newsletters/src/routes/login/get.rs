@@ -0,0 +1,46 @@
+use actix_web::cookie::Cookie;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse};
+
+use super::LOGIN_ERROR_COOKIE;
+
+/*
+    login form for the editors of the newsletter
+ */
+pub async fn login_form(request: HttpRequest) -> HttpResponse {
+    // the cookie only flags the failure, we never echo its content back
+    let error_html = match request.cookie(LOGIN_ERROR_COOKIE) {
+        Some(_) => "<p><i>Authentication failed.</i></p>",
+        None => ""
+    };
+
+    let mut response = HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Login</title>
+</head>
+<body>
+    {error_html}
+    <form action="/login" method="post">
+        <label>Username
+            <input type="text" placeholder="Enter Username" name="username">
+        </label>
+        <label>Password
+            <input type="password" placeholder="Enter Password" name="password">
+        </label>
+        <button type="submit">Login</button>
+    </form>
+</body>
+</html>"#
+        ));
+
+    // the error is only displayed once
+    response
+        .add_removal_cookie(&Cookie::new(LOGIN_ERROR_COOKIE, ""))
+        .expect("the removal cookie is a valid header value");
+    response
+}
@@ -0,0 +1,8 @@
+mod get;
+mod post;
+
+pub use get::login_form;
+pub use post::login;
+
+// cookie flagging a failed login attempt to the login form
+const LOGIN_ERROR_COOKIE: &str = "login_error";
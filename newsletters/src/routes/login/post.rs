@@ -0,0 +1,81 @@
+use actix_web::cookie::Cookie;
+use actix_web::error::InternalError;
+use actix_web::{web, HttpResponse};
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use super::LOGIN_ERROR_COOKIE;
+use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::session_state::TypedSession;
+use crate::utils::{error_chain_fmt, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+    password: Secret<String>
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Something went wrong")]
+    UnexpectedError(#[from] anyhow::Error)
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/*
+    check the submitted credentials and, if they are valid,
+    attach the user to a brand new server-side session
+ */
+#[tracing::instrument(
+    name = "Log in",
+    skip(form, pool, session),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            // a new session key on every login, to prevent session fixation
+            session.renew();
+            session
+                .insert_user_id(user_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            Ok(see_other("/admin/dashboard"))
+        }
+        Err(e) => {
+            let e = match e {
+                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into())
+            };
+            Err(login_redirect(e))
+        }
+    }
+}
+
+/*
+    send the user back to the login form, flagging the failure
+ */
+fn login_redirect(e: LoginError) -> InternalError<LoginError> {
+    let mut response = see_other("/login");
+    response
+        .add_cookie(&Cookie::new(LOGIN_ERROR_COOKIE, "1"))
+        .expect("the login error cookie is a valid header value");
+    InternalError::from_response(e, response)
+}
@@ -0,0 +1,28 @@
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::metrics::metrics;
+
+/*
+    scrape endpoint for Prometheus
+
+    the admin server of a worker-only process has no pool of its own
+    to report on, its loops keep their gauges up to date themselves
+ */
+pub async fn expose_metrics(pool: Option<web::Data<PgPool>>) -> HttpResponse {
+    if let Some(pool) = pool {
+        metrics().observe_pool("api", &pool);
+    }
+
+    match metrics().encode() {
+        Ok((content_type, body)) => HttpResponse::Ok()
+            .content_type(content_type)
+            .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+            .body(body),
+        Err(e) => {
+            tracing::error!(error.message = %e, "failed to encode the metrics");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
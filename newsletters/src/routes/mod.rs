@@ -1,5 +1,17 @@
+mod admin;
 mod health_check;
+mod login;
+mod metrics;
+mod newsletters;
 mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
 
+pub use admin::*;
 pub use health_check::*;
-pub use subscriptions::*;
\ No newline at end of file
+pub use login::*;
+pub use metrics::*;
+pub use newsletters::*;
+pub use subscriptions::*;
+pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
\ No newline at end of file
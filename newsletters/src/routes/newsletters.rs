@@ -0,0 +1,199 @@
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::mailing_list::get_list_by_slug;
+use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
+use crate::issue_rendering::{IssueRenderer, RenderedIssue};
+use crate::issue_scheduler::enqueue_delivery_tasks;
+use crate::utils::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+    // slug of the list, the default one if omitted
+    list: Option<String>,
+    // sent right away if omitted
+    scheduled_at: Option<DateTime<Utc>>
+}
+
+/*
+    either both bodies written by hand, sent as they are,
+    or Markdown that we render with the configured layout
+ */
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    Rendered { html: String, text: String },
+    Markdown { markdown: String }
+}
+
+#[derive(thiserror::Error)]
+pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error)
+}
+
+impl std::fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PublishError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            PublishError::ValidationError(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            PublishError::UnexpectedError(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct PublishedIssue {
+    issue_id: Uuid,
+    status: &'static str,
+    scheduled_at: Option<DateTime<Utc>>
+}
+
+/*
+    handler publishing a new issue of a list,
+    it is queued for delivery to every confirmed member of the list,
+    either right away or by the scheduler once `scheduled_at` is reached
+
+    clients must send an `Idempotency-Key` header: a retry carrying
+    the same key gets the saved response back instead of a second delivery
+ */
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(request, body, pool, renderer, user_id),
+    fields(title = %body.title, user_id = %*user_id)
+)]
+pub async fn publish_newsletter(
+    request: HttpRequest,
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    renderer: web::Data<IssueRenderer>,
+    user_id: web::ReqData<UserId>
+) -> Result<HttpResponse, PublishError> {
+    let user_id = user_id.into_inner();
+    let idempotency_key = idempotency_key(&request).map_err(PublishError::ValidationError)?;
+    let list = get_list_by_slug(pool.get_ref(), body.list.as_deref())
+        .await
+        .context("failed to fetch the mailing list")?
+        .ok_or_else(|| {
+            PublishError::ValidationError(anyhow::anyhow!(
+                "there is no list named '{}'.",
+                body.list.as_deref().unwrap_or_default()
+            ))
+        })?;
+    if let Some(scheduled_at) = body.scheduled_at {
+        if scheduled_at <= Utc::now() {
+            return Err(PublishError::ValidationError(anyhow::anyhow!(
+                "the issue must be scheduled in the future."
+            )));
+        }
+    }
+
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id).await? {
+        NextAction::StartProcessing(transaction) => transaction,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response)
+    };
+
+    let issue = match &body.content {
+        Content::Rendered { html, text } => RenderedIssue {
+            html: html.clone(),
+            text: text.clone()
+        },
+        Content::Markdown { markdown } => renderer.render(&body.title, markdown)
+    };
+
+    // the issue is only stored and queued here, the delivery itself
+    // is carried out by the background workers
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        list.list_id,
+        &body.title,
+        &issue.text,
+        &issue.html,
+        body.scheduled_at
+    )
+    .await
+    .context("failed to store newsletter issue details")?;
+    let status = match body.scheduled_at {
+        Some(_) => "scheduled",
+        None => {
+            enqueue_delivery_tasks(&mut transaction, issue_id, list.list_id)
+                .await
+                .context("failed to enqueue delivery tasks")?;
+            "enqueued"
+        }
+    };
+
+    let response = HttpResponse::Accepted().json(PublishedIssue {
+        issue_id,
+        status,
+        scheduled_at: body.scheduled_at
+    });
+    let response = save_response(transaction, &idempotency_key, *user_id, response).await?;
+    Ok(response)
+}
+
+fn idempotency_key(request: &HttpRequest) -> Result<IdempotencyKey, anyhow::Error> {
+    let header_value = request
+        .headers()
+        .get("Idempotency-Key")
+        .context("the 'Idempotency-Key' header was missing")?
+        .to_str()
+        .context("the 'Idempotency-Key' header was not a valid UTF8 string")?;
+    IdempotencyKey::try_from(header_value.to_owned())
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    scheduled_at: Option<DateTime<Utc>>
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    // a scheduled issue is only published once the scheduler queues it
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            list_id,
+            title,
+            text_content,
+            html_content,
+            status,
+            scheduled_at,
+            published_at
+        )
+        VALUES (
+            $1, $2, $3, $4, $5,
+            CASE WHEN $6::timestamptz IS NULL THEN 'enqueued' ELSE 'scheduled' END,
+            $6,
+            CASE WHEN $6::timestamptz IS NULL THEN now() END
+        )
+        "#,
+        newsletter_issue_id,
+        list_id,
+        title,
+        text_content,
+        html_content,
+        scheduled_at
+        )
+        .execute(transaction)
+        .await?;
+
+    Ok(newsletter_issue_id)
+}
@@ -1,75 +1,584 @@
-use actix_web::{Responder, HttpResponse, web};
-use sqlx::PgPool;
+use actix_web::http::header::{Accept, CacheControl, CacheDirective, ContentType, Header};
+use actix_web::http::StatusCode;
+use actix_web::{mime, web, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use rand::{thread_rng, Rng, distributions::Alphanumeric};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 use chrono::Utc;
 
-use crate::domain::{NewSubscriber, SubscriberName, SubscriberEmail};
+use crate::domain_policy::DomainPolicy;
+use crate::domain::{
+    FormToken, NewSubscriber, NewSubscriberError, SubscriberEmail, SubscriberEmailError, SubscriberNameError
+};
+use crate::email_client::{EmailClient, SendEmailError};
+use crate::mailing_list::{get_list_by_slug, MailingList};
+use crate::metrics::{metrics, SubscriptionOutcome};
+use crate::problem_details::{InvalidParam, ProblemDetails};
+use crate::signup_protection::SignupProtection;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::utils::error_chain_fmt;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email : String,
-    name  : String
+    name  : String,
+    // slug of the list, the default one if omitted
+    list  : Option<String>,
+    // honeypot, hidden from people by the signup forms: only bots fill it in
+    website: Option<String>,
+    // issued by `GET /subscriptions/form-token` when the form was rendered
+    form_token: Option<String>,
+    // set when the subscriber turned down our typo suggestion
+    #[serde(default)]
+    keep_email_as_typed: bool
 }
 
+#[derive(thiserror::Error)]
+pub enum SubscribeError {
+    #[error("unsupported content type '{0}', use application/x-www-form-urlencoded or application/json.")]
+    UnsupportedMediaType(String),
+    #[error("the request body could not be parsed: {0}")]
+    MalformedBody(String),
+    #[error(transparent)]
+    ValidationError(#[from] NewSubscriberError),
+    #[error("there is no list named '{0}'.")]
+    UnknownList(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error)
+}
+
+impl std::fmt::Debug for SubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubscribeError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            SubscribeError::MalformedBody(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::UnknownList(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::UnsupportedMediaType(_) | SubscribeError::MalformedBody(_) => {
+                ProblemDetails::new(self.status_code())
+                    .with_detail(self.to_string())
+                    .to_response()
+            }
+            // one entry per rejected field, so that signup forms
+            // can display the message next to the right input
+            SubscribeError::ValidationError(e) => {
+                let mut problem = ProblemDetails::new(self.status_code()).with_detail(e.to_string());
+                if let Some(e) = &e.name {
+                    problem = problem.with_invalid_param(InvalidParam {
+                        name: "name",
+                        code: name_error_code(e),
+                        reason: e.to_string()
+                    });
+                }
+                if let Some(e) = &e.email {
+                    problem = problem.with_invalid_param(InvalidParam {
+                        name: "email",
+                        code: email_error_code(e),
+                        reason: e.to_string()
+                    });
+                    if let SubscriberEmailError::PossibleTypo(suggestion) = e {
+                        problem = problem.with_suggestion(suggestion);
+                    }
+                }
+                problem.to_response()
+            }
+            SubscribeError::UnknownList(_) => ProblemDetails::new(self.status_code())
+                .with_invalid_param(InvalidParam {
+                    name: "list",
+                    code: "unknown_list",
+                    reason: self.to_string()
+                })
+                .to_response(),
+            SubscribeError::UnexpectedError(_) => HttpResponse::new(self.status_code())
+        }
+    }
+}
+
+impl SubscribeError {
+    fn outcome(&self) -> SubscriptionOutcome {
+        match self {
+            SubscribeError::UnexpectedError(e) if e.downcast_ref::<SendEmailError>().is_some() => {
+                SubscriptionOutcome::EmailError
+            }
+            SubscribeError::UnexpectedError(_) => SubscriptionOutcome::DbError,
+            _ => SubscriptionOutcome::ValidationFailed
+        }
+    }
+}
+
+fn name_error_code(e: &SubscriberNameError) -> &'static str {
+    match e {
+        SubscriberNameError::Empty => "empty",
+        SubscriberNameError::TooLong { .. } => "too_long",
+        SubscriberNameError::ForbiddenCharacter(_) => "forbidden_character"
+    }
+}
+
+fn email_error_code(e: &SubscriberEmailError) -> &'static str {
+    match e {
+        SubscriberEmailError::Empty => "empty",
+        SubscriberEmailError::InvalidSyntax(_) => "invalid_syntax",
+        SubscriberEmailError::DomainNotAllowed(_) => "domain_not_allowed",
+        SubscriberEmailError::DisposableDomain(_) => "disposable_domain",
+        SubscriberEmailError::PossibleTypo(_) => "possible_typo"
+    }
+}
+
+/*
+    the same data can be sent urlencoded, by HTML forms,
+    or as JSON, by our single-page app and mobile clients
+ */
+fn parse_body(request: &HttpRequest, body: &[u8]) -> Result<FormData, SubscribeError> {
+    let mime_type = request.mime_type().ok().flatten();
+
+    match mime_type.as_ref().map(|m| (m.type_(), m.subtype())) {
+        Some((mime::APPLICATION, mime::WWW_FORM_URLENCODED)) => serde_urlencoded::from_bytes(body)
+            .map_err(|e| SubscribeError::MalformedBody(e.to_string())),
+        Some((mime::APPLICATION, mime::JSON)) => serde_json::from_slice(body)
+            .map_err(|e| SubscribeError::MalformedBody(e.to_string())),
+        _ => Err(SubscribeError::UnsupportedMediaType(request.content_type().to_owned()))
+    }
+}
+
+fn prefers_json(request: &HttpRequest) -> bool {
+    Accept::parse(request)
+        .map(|accept| accept.preference() == mime::APPLICATION_JSON)
+        .unwrap_or(false)
+}
+
+fn prefers_html(request: &HttpRequest) -> bool {
+    Accept::parse(request)
+        .map(|accept| accept.preference() == mime::TEXT_HTML)
+        .unwrap_or(false)
+}
+
+/*
+    token to embed in a signup form when rendering it, the signup is
+    rejected if it comes back faster than a human could fill the form
+ */
+pub async fn signup_form_token(hmac_secret: web::Data<HmacSecret>) -> HttpResponse {
+    let form_token = FormToken::generate(Utc::now(), &hmac_secret.0);
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(serde_json::json!({ "form_token": form_token.as_ref() }))
+}
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool),
+    skip(request, body, pool, email_client, base_url, hmac_secret, signup_protection, domain_policy),
     fields(
         // request_id = %Uuid::new_v4(),
-        subscriber_email = %form.email,
-        subscriber_name = %form.name
+        subscriber_email = tracing::field::Empty,
+        subscriber_name = tracing::field::Empty,
+        bot_signal = tracing::field::Empty
     )
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscriptions(
-    form : actix_web::web::Form<FormData>,
-    pool: web::Data<PgPool>
-) -> impl Responder{
+    request: HttpRequest,
+    body: web::Bytes,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    signup_protection: web::Data<SignupProtection>,
+    domain_policy: web::Data<DomainPolicy>
+) -> Result<HttpResponse, SubscribeError> {
+    let outcome = subscribe(
+        &request,
+        &body,
+        &pool,
+        &email_client,
+        &base_url,
+        &hmac_secret,
+        &signup_protection,
+        &domain_policy
+    )
+    .await;
+
+    metrics().record_subscription(match &outcome {
+        Ok((outcome, _)) => *outcome,
+        Err(e) => e.outcome()
+    });
+    outcome.map(|(_, response)| response)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn subscribe(
+    request: &HttpRequest,
+    body: &[u8],
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    signup_protection: &SignupProtection,
+    domain_policy: &DomainPolicy
+) -> Result<(SubscriptionOutcome, HttpResponse), SubscribeError> {
+    let form = parse_body(request, body)?;
+    tracing::Span::current()
+        .record("subscriber_email", tracing::field::display(&form.email))
+        .record("subscriber_name", tracing::field::display(&form.name));
+
+    // bots get the same response as everybody else, so that
+    // they have no reason to try harder, but nothing is stored
+    if let Err(signal) = signup_protection.check(
+        form.website.as_deref(),
+        form.form_token.as_deref(),
+        &hmac_secret.0,
+        Utc::now()
+    ) {
+        tracing::Span::current().record("bot_signal", signal.as_str());
+        tracing::warn!(bot_signal = signal.as_str(), "signup rejected as the work of a bot");
+        return Ok((SubscriptionOutcome::BotRejected, success_response(request)));
+    }
+    let list_slug = form.list.clone();
+    let form_token = form.form_token.clone();
+    let keep_email_as_typed = form.keep_email_as_typed;
+    let new_subscriber = NewSubscriber::try_from(form)?;
+    domain_policy
+        .check(&new_subscriber.email)
+        .await
+        .map_err(|e| NewSubscriberError { name: None, email: Some(e) })?;
+    if !keep_email_as_typed {
+        if let Some(suggestion) = new_subscriber.email.suggestion() {
+            // browsers submitting our forms get a page to pick either address
+            if prefers_html(request) {
+                let page = typo_page(&new_subscriber, &suggestion, list_slug.as_deref(), form_token.as_deref());
+                return Ok((SubscriptionOutcome::ValidationFailed, page));
+            }
+            let e = SubscriberEmailError::PossibleTypo(suggestion);
+            return Err(NewSubscriberError { name: None, email: Some(e) }.into());
+        }
+    }
+
+    let list = get_list_by_slug(pool, list_slug.as_deref())
+        .await
+        .context("failed to fetch the mailing list")?
+        .ok_or_else(|| SubscribeError::UnknownList(list_slug.unwrap_or_default()))?;
+
+    // the subscriber and its token must be stored together,
+    // or not at all
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to acquire a Postgres connection from the pool")?;
 
-    let new_subscriber = match NewSubscriber::try_from(form.0) {
-        Ok(subscriber) => subscriber,
-        Err(_) => return HttpResponse::BadRequest().finish()
+    let inserted_id = insert_subscriber(&mut transaction, &new_subscriber)
+        .await
+        .context("failed to insert new subscriber in the database")?;
+    let subscriber_id = match inserted_id {
+        Some(subscriber_id) => subscriber_id,
+        // somebody signed up with this address before, maybe to another list
+        None => get_existing_subscriber_id(&mut transaction, &new_subscriber)
+            .await
+            .context("failed to fetch the existing subscriber")?
     };
 
-    match insert_subscriber(&pool, &new_subscriber).await {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish()
+    let joined = insert_membership(&mut transaction, list.list_id, subscriber_id)
+        .await
+        .context("failed to add the subscriber to the list")?;
+    let mut outcome = SubscriptionOutcome::Accepted;
+    if !joined {
+        let status = get_membership_status(&mut transaction, list.list_id, subscriber_id)
+            .await
+            .context("failed to fetch the existing list membership")?;
+        match status.as_str() {
+            // the first email may have been lost, send a fresh link
+            "pending_confirmation" => outcome = SubscriptionOutcome::Duplicate,
+            // opting back in goes through the confirmation again
+            "unsubscribed" => {
+                mark_membership_as_pending(&mut transaction, list.list_id, subscriber_id)
+                    .await
+                    .context("failed to mark the subscriber as pending confirmation")?;
+                // only the link we are about to send may confirm the membership
+                delete_subscription_tokens(&mut transaction, subscriber_id, list.list_id)
+                    .await
+                    .context("failed to revoke the previous confirmation tokens")?;
+            }
+            // the response must not reveal that the address
+            // is already on the list
+            _ => return Ok((SubscriptionOutcome::Duplicate, success_response(request)))
+        }
     }
 
+    let subscription_token = generate_subscription_token();
+    store_token(&mut transaction, subscriber_id, list.list_id, &subscription_token)
+        .await
+        .context("failed to store the confirmation token for a new subscriber")?;
+
+    transaction
+        .commit()
+        .await
+        .context("failed to commit SQL transaction to store a new subscriber")?;
+
+    send_confirmation_email(
+        email_client,
+        &new_subscriber.email,
+        &list,
+        &base_url.0,
+        &subscription_token
+    )
+    .await
+    .context("failed to send a confirmation email")?;
+
+    Ok((outcome, success_response(request)))
+}
+
+/*
+    "did you mean...?" page, with one form resubmitting the suggested
+    address and one keeping the address as it was typed
+ */
+fn typo_page(
+    new_subscriber: &NewSubscriber,
+    suggestion: &str,
+    list: Option<&str>,
+    form_token: Option<&str>
+) -> HttpResponse {
+    let hidden = |name: &str, value: &str| {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            name,
+            htmlescape::encode_attribute(value)
+        )
+    };
+    let mut common_fields = vec![hidden("name", new_subscriber.name.as_ref())];
+    common_fields.extend(list.map(|list| hidden("list", list)));
+    common_fields.extend(form_token.map(|form_token| hidden("form_token", form_token)));
+    let common_fields = common_fields.join("\n        ");
+    let suggested_email = hidden("email", suggestion);
+    let typed_email = hidden("email", new_subscriber.email.as_ref());
+    let keep_email_as_typed = hidden("keep_email_as_typed", "true");
+    let suggestion = htmlescape::encode_minimal(suggestion);
+    let typed = htmlescape::encode_minimal(new_subscriber.email.as_ref());
+
+    HttpResponse::BadRequest()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Check your email address</title>
+</head>
+<body>
+    <p>Did you mean <strong>{suggestion}</strong>?</p>
+    <form action="/subscriptions" method="post">
+        {common_fields}
+        {suggested_email}
+        <button type="submit">Yes, use {suggestion}</button>
+    </form>
+    <form action="/subscriptions" method="post">
+        {common_fields}
+        {typed_email}
+        {keep_email_as_typed}
+        <button type="submit">No, {typed} is right</button>
+    </form>
+</body>
+</html>"#
+        ))
+}
+
+fn success_response(request: &HttpRequest) -> HttpResponse {
+    if prefers_json(request) {
+        HttpResponse::Ok().json(serde_json::json!({
+            "message": "Check your inbox to confirm your subscription."
+        }))
+    } else {
+        HttpResponse::Ok().finish()
+    }
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = NewSubscriberError;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-
-        Ok(NewSubscriber {email, name})
+        NewSubscriber::parse(value.name, value.email)
     }
 }
 
+/*
+    returns `None` if the email address is already in use, in any case,
+    the unique constraint resolves concurrent signups for us
+ */
 #[tracing::instrument(
     name = "saving new subscriber to the database",
-    skip(pool, new_subscriber),
+    skip(transaction, new_subscriber),
 )]
 pub async fn insert_subscriber(
-    pool: &PgPool,
+    transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber_id = Uuid::new_v4();
+    let n_inserted_rows = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO subscriptions (id, email, email_canonical, name, subscribed_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (email_canonical) DO NOTHING
         "#,
-        Uuid::new_v4(),
+        subscriber_id,
         new_subscriber.email.as_ref(),
+        new_subscriber.email.canonical(),
         new_subscriber.name.as_ref(),
         Utc::now()
         )
-        // We use `get_ref` to get an immutable reference to the `PgConnection`
-        // wrapped by `web::Data`.
-        .execute(pool)
+        .execute(transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to execute query: {:?}", e);
+            e
+        })?
+        .rows_affected();
+
+    Ok((n_inserted_rows > 0).then_some(subscriber_id))
+}
+
+#[tracing::instrument(
+    name = "get the existing subscriber with the same email",
+    skip(transaction, new_subscriber)
+)]
+pub async fn get_existing_subscriber_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber
+) -> Result<Uuid, sqlx::Error> {
+    // lock the row, so that concurrent signups with the
+    // same address are handled one after the other
+    let subscriber = sqlx::query!(
+        r#"SELECT id FROM subscriptions WHERE email_canonical = $1 FOR UPDATE"#,
+        new_subscriber.email.canonical()
+        )
+        .fetch_one(transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to execute query: {:?}", e);
+            e
+        })?;
+
+    Ok(subscriber.id)
+}
+
+/*
+    returns `false` if the subscriber already belongs to the list,
+    whatever the status of their membership
+ */
+#[tracing::instrument(
+    name = "add the subscriber to the list",
+    skip(transaction)
+)]
+pub async fn insert_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid
+) -> Result<bool, sqlx::Error> {
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+        VALUES ($1, $2, 'pending_confirmation', $3)
+        ON CONFLICT (list_id, subscriber_id) DO NOTHING
+        "#,
+        list_id,
+        subscriber_id,
+        Utc::now()
+        )
+        .execute(transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to execute query: {:?}", e);
+            e
+        })?
+        .rows_affected();
+
+    Ok(n_inserted_rows > 0)
+}
+
+#[tracing::instrument(
+    name = "get the status of the list membership",
+    skip(transaction)
+)]
+pub async fn get_membership_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid
+) -> Result<String, sqlx::Error> {
+    let membership = sqlx::query!(
+        r#"
+        SELECT status FROM list_memberships
+        WHERE list_id = $1 AND subscriber_id = $2
+        "#,
+        list_id,
+        subscriber_id
+        )
+        .fetch_one(transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to execute query: {:?}", e);
+            e
+        })?;
+
+    Ok(membership.status)
+}
+
+#[tracing::instrument(
+    name = "mark subscriber as pending confirmation",
+    skip(transaction)
+)]
+pub async fn mark_membership_as_pending(
+    transaction: &mut Transaction<'_, Postgres>,
+    list_id: Uuid,
+    subscriber_id: Uuid
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships SET status = 'pending_confirmation'
+        WHERE list_id = $1 AND subscriber_id = $2
+        "#,
+        list_id,
+        subscriber_id
+        )
+        .execute(transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to execute query: {:?}", e);
+            e
+        })?;
+
+    Ok(())
+}
+
+/*
+    revoke the confirmation links sent so far for this membership,
+    so that a link from an older email cannot confirm it again
+ */
+#[tracing::instrument(
+    name = "delete the subscription tokens of a membership",
+    skip(transaction)
+)]
+pub async fn delete_subscription_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM subscription_tokens
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+        )
+        .execute(transaction)
         .await
         .map_err(|e| {
             tracing::error!("failed to execute query: {:?}", e);
@@ -78,3 +587,81 @@ pub async fn insert_subscriber(
 
     Ok(())
 }
+
+#[tracing::instrument(
+    name = "store subscription token in the database",
+    skip(transaction, subscription_token)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    subscription_token: &str
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id)
+        VALUES ($1, $2, $3)
+        "#,
+        subscription_token,
+        subscriber_id,
+        list_id
+        )
+        .execute(transaction)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to execute query: {:?}", e);
+            e
+        })?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "send a confirmation email to a new subscriber",
+    skip(email_client, subscriber_email, list, base_url, subscription_token)
+)]
+pub async fn send_confirmation_email(
+    email_client: &EmailClient,
+    subscriber_email: &SubscriberEmail,
+    list: &MailingList,
+    base_url: &str,
+    subscription_token: &str
+) -> Result<(), SendEmailError> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url,
+        subscription_token
+    );
+    let html_body = format!(
+        "Welcome to {}!<br />\
+        Click <a href=\"{}\">here</a> to confirm your subscription.",
+        htmlescape::encode_minimal(&list.name),
+        confirmation_link
+    );
+    let plain_body = format!(
+        "Welcome to {}!\nVisit {} to confirm your subscription.",
+        list.name,
+        confirmation_link
+    );
+
+    email_client
+        .send_email(subscriber_email, "Welcome!", &html_body, &plain_body)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to send the confirmation email: {:?}", e);
+            e
+        })
+}
+
+/*
+    generate a random 25-characters-long case-sensitive subscription token
+    it is used as the proof that the subscriber owns the email address
+ */
+pub fn generate_subscription_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(25)
+        .collect()
+}
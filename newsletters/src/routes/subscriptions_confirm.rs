@@ -0,0 +1,106 @@
+use actix_web::{web, HttpResponse, Responder};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    subscription_token: String
+}
+
+/*
+    handler for the link sent in the confirmation email
+    it confirms the membership the token was issued for,
+    the subscriber's other lists are left alone
+ */
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, pool)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>
+) -> impl Responder {
+    let membership = match get_membership_from_token(
+        &pool,
+        &parameters.subscription_token
+    )
+    .await
+    {
+        Ok(membership) => membership,
+        Err(_) => return HttpResponse::InternalServerError().finish()
+    };
+
+    match membership {
+        // nobody owns this token
+        None => HttpResponse::Unauthorized().finish(),
+        Some(membership) => {
+            let confirmed = confirm_subscriber(&pool, membership.subscriber_id, membership.list_id).await;
+            if confirmed.is_err() {
+                return HttpResponse::InternalServerError().finish();
+            }
+            HttpResponse::Ok().finish()
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "mark subscriber as confirmed",
+    skip(pool)
+)]
+pub async fn confirm_subscriber(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list_id: Uuid
+) -> Result<(), sqlx::Error> {
+    // a link from an older confirmation email must not
+    // bring back somebody who unsubscribed since then
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships SET status = 'confirmed'
+        WHERE
+            subscriber_id = $1 AND
+            list_id = $2 AND
+            status = 'pending_confirmation'
+        "#,
+        subscriber_id,
+        list_id
+        )
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to execute query: {:?}", e);
+            e
+        })?;
+
+    Ok(())
+}
+
+pub struct TokenMembership {
+    pub subscriber_id: Uuid,
+    pub list_id: Uuid
+}
+
+#[tracing::instrument(
+    name = "get the list membership from token",
+    skip(pool, subscription_token)
+)]
+pub async fn get_membership_from_token(
+    pool: &PgPool,
+    subscription_token: &str
+) -> Result<Option<TokenMembership>, sqlx::Error> {
+    sqlx::query_as!(
+        TokenMembership,
+        r#"
+        SELECT subscriber_id, list_id
+        FROM subscription_tokens
+        WHERE subscription_token = $1
+        "#,
+        subscription_token
+        )
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to execute query: {:?}", e);
+            e
+        })
+}
@@ -0,0 +1,172 @@
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::UnsubscribeToken;
+use crate::mailing_list::{get_list, get_list_by_slug, MailingList};
+use crate::routes::delete_subscription_tokens;
+use crate::startup::HmacSecret;
+use crate::utils::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("{0}")]
+    InvalidToken(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error)
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/*
+    landing page of the unsubscribe link sent with every issue
+
+    it only asks for a confirmation: link scanners and mail clients
+    prefetch GET urls, they must not unsubscribe anybody
+ */
+#[tracing::instrument(name = "Show the unsubscribe page", skip(parameters, pool, hmac_secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>
+) -> Result<HttpResponse, UnsubscribeError> {
+    let token = UnsubscribeToken::parse(parameters.0.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    let list = get_token_list(&pool, &token)
+        .await
+        .context("failed to fetch the mailing list")?;
+    let list_name = htmlescape::encode_minimal(&list.name);
+    let action = htmlescape::encode_minimal(&format!(
+        "/subscriptions/unsubscribe?token={}",
+        token.as_ref()
+    ));
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Do you want to stop receiving {list_name}?</p>
+    <form action="{action}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#
+        )))
+}
+
+/*
+    handler unsubscribing the owner of the token from the list
+    the token was issued for, their other lists are left alone
+
+    it is also the target of one-click unsubscribe requests (RFC 8058),
+    which carry `List-Unsubscribe=One-Click` as their body: the token
+    in the query string is all we need, so the body is ignored
+ */
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, pool, hmac_secret),
+    fields(
+        subscriber_id = tracing::field::Empty,
+        list = tracing::field::Empty
+    )
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>
+) -> Result<HttpResponse, UnsubscribeError> {
+    let token = UnsubscribeToken::parse(parameters.0.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    let list = get_token_list(&pool, &token)
+        .await
+        .context("failed to fetch the mailing list")?;
+    tracing::Span::current()
+        .record("subscriber_id", tracing::field::display(token.subscriber_id()))
+        .record("list", tracing::field::display(&list.slug));
+
+    mark_subscriber_as_unsubscribed(&pool, token.subscriber_id(), list.list_id)
+        .await
+        .context("failed to unsubscribe the subscriber")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed, you will not receive any further issue of this list.</p>
+</body>
+</html>"#
+        ))
+}
+
+/*
+    unsubscribing twice is not an error, the link
+    in an older issue keeps working
+
+    the confirmation links sent so far are revoked along the way,
+    opting back in requires a new one
+ */
+#[tracing::instrument(name = "mark subscriber as unsubscribed", skip(pool))]
+async fn mark_subscriber_as_unsubscribed(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list_id: Uuid
+) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships SET status = 'unsubscribed'
+        WHERE subscriber_id = $1 AND list_id = $2
+        "#,
+        subscriber_id,
+        list_id
+        )
+        .execute(&mut transaction)
+        .await?;
+    delete_subscription_tokens(&mut transaction, subscriber_id, list_id).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+// links sent before we had several lists were all about the default one
+async fn get_token_list(pool: &PgPool, token: &UnsubscribeToken) -> Result<MailingList, sqlx::Error> {
+    match token.list_id() {
+        Some(list_id) => get_list(pool, list_id).await,
+        None => get_list_by_slug(pool, None)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+}
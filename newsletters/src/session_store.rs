@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use actix_session::storage::{
+    generate_session_key, LoadError, SaveError, SessionKey, SessionStore, UpdateError
+};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+type SessionState = HashMap<String, String>;
+
+/*
+    session storage backend keeping the state of every session
+    in the `sessions` table, the cookie only carries the session key
+ */
+#[derive(Clone)]
+pub struct PgSessionStore {
+    pool: PgPool
+}
+
+impl PgSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+fn expires_at(ttl: &Duration) -> chrono::DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(ttl.whole_seconds())
+}
+
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT state
+            FROM sessions
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref()
+            )
+            .fetch_optional(&self.pool)
+            .await
+            .context("failed to load the session state")
+            .map_err(LoadError::Other)?;
+
+        row.map(|r| serde_json::from_value(r.state))
+            .transpose()
+            .context("failed to deserialize the session state")
+            .map_err(LoadError::Deserialization)
+    }
+
+    async fn save(&self, session_state: SessionState, ttl: &Duration) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_value(&session_state)
+            .context("failed to serialize the session state")
+            .map_err(SaveError::Serialization)?;
+        let session_key = generate_session_key();
+
+        // piggyback on new sessions to get rid of the expired ones
+        sqlx::query!("DELETE FROM sessions WHERE expires_at <= now()")
+            .execute(&self.pool)
+            .await
+            .context("failed to delete expired sessions")
+            .map_err(SaveError::Other)?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key, state, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl)
+            )
+            .execute(&self.pool)
+            .await
+            .context("failed to save the session state")
+            .map_err(SaveError::Other)?;
+
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_value(&session_state)
+            .context("failed to serialize the session state")
+            .map_err(UpdateError::Serialization)?;
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET state = $2, expires_at = $3
+            WHERE session_key = $1
+            "#,
+            session_key.as_ref(),
+            state,
+            expires_at(ttl)
+            )
+            .execute(&self.pool)
+            .await
+            .context("failed to update the session state")
+            .map_err(UpdateError::Other)?;
+
+        if result.rows_affected() == 0 {
+            // the session expired in the meantime, start a new one
+            self.save(session_state, ttl).await.map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e)
+            })
+        } else {
+            Ok(session_key)
+        }
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            "UPDATE sessions SET expires_at = $2 WHERE session_key = $1",
+            session_key.as_ref(),
+            expires_at(ttl)
+            )
+            .execute(&self.pool)
+            .await
+            .context("failed to update the session ttl")?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            "DELETE FROM sessions WHERE session_key = $1",
+            session_key.as_ref()
+            )
+            .execute(&self.pool)
+            .await
+            .context("failed to delete the session")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use chrono::{DateTime, Duration, Utc};
+use secrecy::Secret;
+
+use crate::domain::FormToken;
+
+/// Why a signup was taken for the work of a bot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotSignal {
+    // the hidden field was filled in
+    Honeypot,
+    MissingFormToken,
+    // forged, or signed with a secret we no longer use
+    InvalidFormToken,
+    // submitted faster than anybody can type
+    TooFast,
+    // rendered so long ago that it was probably harvested
+    StaleFormToken
+}
+
+impl BotSignal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BotSignal::Honeypot => "honeypot",
+            BotSignal::MissingFormToken => "missing_form_token",
+            BotSignal::InvalidFormToken => "invalid_form_token",
+            BotSignal::TooFast => "too_fast",
+            BotSignal::StaleFormToken => "stale_form_token"
+        }
+    }
+}
+
+/*
+    cheap checks telling bots apart from people filling in a signup form:
+    people leave the hidden honeypot field empty, and take a few seconds
+    between the moment the form is rendered and its submission
+ */
+#[derive(Debug, Clone)]
+pub struct SignupProtection {
+    min_fill_time: Duration,
+    max_form_age: Duration,
+    // signups without a form token, from older forms or API clients,
+    // are let through unless this is set
+    require_form_token: bool
+}
+
+impl SignupProtection {
+    pub fn new(min_fill_time: Duration, max_form_age: Duration, require_form_token: bool) -> Self {
+        Self {
+            min_fill_time,
+            max_form_age,
+            require_form_token
+        }
+    }
+
+    pub fn check(
+        &self,
+        honeypot: Option<&str>,
+        form_token: Option<&str>,
+        hmac_secret: &Secret<String>,
+        now: DateTime<Utc>
+    ) -> Result<(), BotSignal> {
+        if honeypot.is_some_and(|v| !v.trim().is_empty()) {
+            return Err(BotSignal::Honeypot);
+        }
+
+        let form_token = match form_token {
+            Some(form_token) => form_token,
+            None if self.require_form_token => return Err(BotSignal::MissingFormToken),
+            None => return Ok(())
+        };
+        let form_token = FormToken::parse(form_token.to_owned(), hmac_secret)
+            .map_err(|_| BotSignal::InvalidFormToken)?;
+        let fill_time = now - form_token.issued_at();
+        if fill_time < self.min_fill_time {
+            return Err(BotSignal::TooFast);
+        }
+        if fill_time > self.max_form_age {
+            return Err(BotSignal::StaleFormToken);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BotSignal, SignupProtection};
+    use crate::domain::FormToken;
+    use chrono::{Duration, Utc};
+    use secrecy::Secret;
+
+    fn secret() -> Secret<String> {
+        Secret::new("super-long-and-secret-random-key-needed-to-verify-message-integrity".into())
+    }
+
+    fn protection(require_form_token: bool) -> SignupProtection {
+        SignupProtection::new(Duration::seconds(3), Duration::hours(24), require_form_token)
+    }
+
+    fn token_issued_ago(age: Duration) -> String {
+        FormToken::generate(Utc::now() - age, &secret()).as_ref().to_owned()
+    }
+
+    #[test]
+    fn a_patient_human_is_let_through() {
+        let token = token_issued_ago(Duration::seconds(10));
+
+        let outcome = protection(true).check(Some(""), Some(&token), &secret(), Utc::now());
+
+        assert_eq!(outcome, Ok(()));
+    }
+
+    #[test]
+    fn a_filled_honeypot_gives_the_bot_away() {
+        let token = token_issued_ago(Duration::seconds(10));
+
+        let outcome = protection(false).check(Some("https://spam.example"), Some(&token), &secret(), Utc::now());
+
+        assert_eq!(outcome, Err(BotSignal::Honeypot));
+    }
+
+    #[test]
+    fn forms_submitted_too_fast_are_rejected() {
+        let token = token_issued_ago(Duration::zero());
+
+        let outcome = protection(false).check(None, Some(&token), &secret(), Utc::now());
+
+        assert_eq!(outcome, Err(BotSignal::TooFast));
+    }
+
+    #[test]
+    fn stale_form_tokens_are_rejected() {
+        let token = token_issued_ago(Duration::days(2));
+
+        let outcome = protection(false).check(None, Some(&token), &secret(), Utc::now());
+
+        assert_eq!(outcome, Err(BotSignal::StaleFormToken));
+    }
+
+    #[test]
+    fn forged_form_tokens_are_rejected() {
+        let outcome = protection(false).check(None, Some("1685000000.c2lnbmF0dXJl"), &secret(), Utc::now());
+
+        assert_eq!(outcome, Err(BotSignal::InvalidFormToken));
+    }
+
+    #[test]
+    fn the_form_token_is_only_required_if_configured() {
+        assert_eq!(protection(false).check(None, None, &secret(), Utc::now()), Ok(()));
+        assert_eq!(
+            protection(true).check(None, None, &secret(), Utc::now()),
+            Err(BotSignal::MissingFormToken)
+        );
+    }
+}
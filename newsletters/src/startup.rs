@@ -1,24 +1,222 @@
 use std::{net::TcpListener};
-use actix_web::{web, HttpServer, App, dev::Server};
-use sqlx::{PgPool};
-use crate::routes::{health_check, subscriptions};
+use actix_session::SessionMiddleware;
+use actix_web::{web, HttpServer, App, cookie::Key, dev::Server, middleware::from_fn};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgPool, postgres::PgPoolOptions};
+use crate::configuration::{DatabaseSettings, Settings};
+use crate::authentication::{reject_anonymous_users, reject_invalid_basic_credentials};
+use crate::domain_policy::DomainPolicy;
+use crate::email_client::EmailClient;
+use crate::issue_rendering::IssueRenderer;
+use crate::metrics::record_http_metrics;
+use crate::rate_limiting::{rate_limit, RateLimiter};
+use crate::signup_protection::SignupProtection;
+use crate::routes::{
+    EmailCheckCache, admin_dashboard, cancel_issue, change_password, change_password_form, confirm, create_list,
+    expose_metrics, export_subscribers, get_subscriber, health_check, import_subscribers, list_lists,
+    list_scheduled_issues, list_subscribers, liveness, log_out, login, login_form, preview_newsletter,
+    publish_newsletter, readiness, reschedule_issue, signup_form_token, subscriptions, unsubscribe,
+    unsubscribe_form, update_list
+};
+use crate::session_store::PgSessionStore;
 use tracing_actix_web::TracingLogger;
 
+/*
+    wrapper type to retrieve the public url of the application
+    from the application state, `String` alone would be ambiguous
+ */
+pub struct ApplicationBaseUrl(pub String);
+
+// large enough for a list of a few hundred thousand subscribers
+const IMPORT_PAYLOAD_LIMIT: usize = 64 * 1024 * 1024;
+
+/*
+    secret used to sign the unsubscribe links,
+    wrapped for the same reason as `ApplicationBaseUrl`
+ */
+#[derive(Clone)]
+pub struct HmacSecret(pub Secret<String>);
+
+pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
+    PgPoolOptions::new()
+        // `connect_lazy_with` instead of `connect_lazy`
+        .connect_lazy_with(configuration.with_db())
+}
+
+/*
+    build the API server from the configuration
+    and serve requests until the process is stopped
+ */
+pub async fn run_api_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let address = format!("{}:{}", configuration.application.host, configuration.application.port);
+    let connection_pool = get_connection_pool(&configuration.database);
+
+    let email_client = configuration.email_client.client()
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("invalid email client configuration")?;
+    let issue_renderer = configuration.issues.renderer()
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("invalid issue layout")?;
+    let domain_policy = configuration.domain_policy.policy()
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("invalid domain policy")?;
+    let rate_limiter = configuration.rate_limiting.limiter()
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("invalid rate limiting configuration")?;
+
+    let listener = TcpListener::bind(address)?;
+    let server = run(
+        listener,
+        connection_pool.clone(),
+        email_client,
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+        issue_renderer,
+        rate_limiter,
+        configuration.signup_protection.protection(),
+        domain_policy
+    )?;
+
+    let admin_listener = TcpListener::bind((
+        configuration.metrics.host.as_str(),
+        configuration.metrics.admin_port
+    ))?;
+    let admin_server = run_admin(admin_listener, Some(connection_pool))?;
+    tokio::try_join!(server, admin_server)?;
+
+    Ok(())
+}
+
+/*
+    serve the metrics of a process running the background loops only
+ */
+pub async fn run_admin_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let listener = TcpListener::bind((
+        configuration.metrics.host.as_str(),
+        configuration.metrics.admin_port
+    ))?;
+    run_admin(listener, None)?.await?;
+    Ok(())
+}
+
+/*
+    internal server, on its own port, exposing the metrics only
+
+    the public API never serves them: they tell a lot about our traffic
+    and are not worth an authentication scheme of their own
+ */
+pub fn run_admin(listener: TcpListener, db_pool: Option<PgPool>) -> std::io::Result<Server> {
+    let pool = db_pool.map(web::Data::new);
+
+    let server = HttpServer::new(move || {
+        let app = App::new()
+            .wrap(TracingLogger::default())
+            .route("/metrics", web::get().to(expose_metrics));
+        match &pool {
+            Some(pool) => app.app_data(pool.clone()),
+            None => app
+        }
+    })
+    // scrapes are few and far between
+    .workers(1)
+    .listen(listener)?
+    .run();
+
+    Ok(server)
+}
+
 /*
     Create http web server with contain an app
     to handle Http requests parser, routine to request handler
 */
-pub fn run(listener: TcpListener, db_pool: PgPool) -> std::io::Result<Server> {
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    listener: TcpListener,
+    db_pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    hmac_secret: Secret<String>,
+    issue_renderer: IssueRenderer,
+    rate_limiter: RateLimiter,
+    signup_protection: SignupProtection,
+    domain_policy: DomainPolicy
+) -> std::io::Result<Server> {
     
+    // the admin sessions live in the database, next to the rest of our state
+    let session_store = PgSessionStore::new(db_pool.clone());
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let pool = web::Data::new(db_pool);
+    let email_client = web::Data::new(email_client);
+    let email_check = web::Data::new(EmailCheckCache::default());
+    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let hmac_secret = web::Data::new(HmacSecret(hmac_secret));
+    let issue_renderer = web::Data::new(issue_renderer);
+    // shared by every worker thread, or each would have its own buckets
+    let rate_limiter = web::Data::new(rate_limiter);
+    let signup_protection = web::Data::new(signup_protection);
+    let domain_policy = web::Data::new(domain_policy);
 
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(SessionMiddleware::new(session_store.clone(), secret_key.clone()))
+            // throttled requests are still logged, but never reach the sessions
+            .wrap(from_fn(rate_limit))
+            // throttled requests are counted as well, under the span of the request
+            .wrap(from_fn(record_http_metrics))
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
+            .route("/health/live", web::get().to(liveness))
+            .route("/health/ready", web::get().to(readiness))
             .route("/subscriptions", web::post().to(subscriptions))
+            .route("/subscriptions/form-token", web::get().to(signup_form_token))
+            .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/subscriptions/unsubscribe", web::get().to(unsubscribe_form))
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .service(
+                web::resource("/newsletters")
+                    .wrap(from_fn(reject_invalid_basic_credentials))
+                    .route(web::post().to(publish_newsletter))
+            )
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/newsletters", web::post().to(publish_newsletter))
+                    // before `{issue_id}`, which would match them as well
+                    .route("/newsletters/preview", web::post().to(preview_newsletter))
+                    .route("/newsletters/scheduled", web::get().to(list_scheduled_issues))
+                    .route("/newsletters/{issue_id}", web::patch().to(reschedule_issue))
+                    .route("/newsletters/{issue_id}/cancel", web::post().to(cancel_issue))
+                    .route("/lists", web::get().to(list_lists))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/lists/{slug}", web::patch().to(update_list))
+                    .route("/subscribers", web::get().to(list_subscribers))
+                    // before `{subscriber_id}`, which would match it as well
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .service(
+                        web::resource("/subscribers/import")
+                            // imported lists are much larger than any other payload
+                            .app_data(web::PayloadConfig::new(IMPORT_PAYLOAD_LIMIT))
+                            .route(web::post().to(import_subscribers))
+                    )
+                    .route("/subscribers/{subscriber_id}", web::get().to(get_subscriber))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/logout", web::post().to(log_out))
+            )
             // register the connection as part of the application state
             .app_data(pool.clone())
+            .app_data(email_client.clone())
+            .app_data(email_check.clone())
+            .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(issue_renderer.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(signup_protection.clone())
+            .app_data(domain_policy.clone())
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,63 @@
+use actix_web::web::Bytes;
+use futures_util::{Stream, TryStreamExt};
+use sqlx::PgPool;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::subscriber_filter::{SubscriberFilter, SubscriberSummary};
+
+// rows are buffered up to this size before being handed over
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/*
+    CSV export of the subscribers matching the filter
+
+    rows are pulled from the database as the consumer reads the stream,
+    only a single chunk is ever kept in memory
+ */
+pub fn subscribers_csv(
+    pool: PgPool,
+    filter: SubscriberFilter
+) -> impl Stream<Item = Result<Bytes, anyhow::Error>> {
+    async_stream::try_stream! {
+        // the header is written by hand, so that an
+        // empty export still carries it
+        let mut writer = chunk_writer();
+        writer.write_record(["id", "email", "name", "status", "subscribed_at"])?;
+
+        let mut query = filter.select_subscribers();
+        query.push(" ORDER BY m.subscribed_at, m.subscriber_id");
+        let mut rows = query.build_query_as::<SubscriberSummary>().fetch(&pool);
+        while let Some(subscriber) = rows.try_next().await? {
+            writer.serialize(&subscriber)?;
+            writer.flush()?;
+            if writer.get_ref().len() >= CHUNK_SIZE {
+                let chunk = std::mem::replace(&mut writer, chunk_writer()).into_inner()?;
+                yield Bytes::from(chunk);
+            }
+        }
+
+        yield Bytes::from(writer.into_inner()?);
+    }
+}
+
+fn chunk_writer() -> csv::Writer<Vec<u8>> {
+    csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::with_capacity(CHUNK_SIZE))
+}
+
+/// Write the CSV export to `output`, e.g. a file or the standard output.
+pub async fn write_subscribers_csv(
+    pool: PgPool,
+    filter: SubscriberFilter,
+    mut output: impl AsyncWrite + Unpin
+) -> Result<(), anyhow::Error> {
+    let chunks = subscribers_csv(pool, filter);
+    futures_util::pin_mut!(chunks);
+    while let Some(chunk) = chunks.try_next().await? {
+        output.write_all(&chunk).await?;
+    }
+    output.flush().await?;
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::mailing_list::DEFAULT_LIST_SLUG;
+use crate::problem_details::InvalidParam;
+
+const STATUSES: [&str; 3] = ["pending_confirmation", "confirmed", "unsubscribed"];
+
+/*
+    filters shared by the admin listing and the CSV exports,
+    they always apply to the members of a single list
+
+    every parameter is optional and kept as a string, so that
+    we can report all the invalid ones at once
+ */
+#[derive(serde::Deserialize, Default, Debug)]
+pub struct FilterParameters {
+    // slug of the list, the default one if omitted
+    pub list: Option<String>,
+    pub status: Option<String>,
+    pub subscribed_after: Option<String>,
+    pub subscribed_before: Option<String>,
+    pub search: Option<String>
+}
+
+#[derive(Default, Debug)]
+pub struct SubscriberFilter {
+    list: String,
+    status: Option<String>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    search: Option<String>
+}
+
+impl TryFrom<FilterParameters> for SubscriberFilter {
+    type Error = Vec<InvalidParam>;
+
+    fn try_from(value: FilterParameters) -> Result<Self, Self::Error> {
+        let mut invalid_params = Vec::new();
+        let mut filter = SubscriberFilter {
+            list: value.list.unwrap_or_else(|| DEFAULT_LIST_SLUG.into()),
+            search: value.search.filter(|s| !s.is_empty()),
+            ..Default::default()
+        };
+
+        if let Some(status) = value.status {
+            if STATUSES.contains(&status.as_str()) {
+                filter.status = Some(status);
+            } else {
+                invalid_params.push(InvalidParam {
+                    name: "status",
+                    code: "unknown_status",
+                    reason: format!("the status must be one of {}.", STATUSES.join(", "))
+                });
+            }
+        }
+        for (name, value, bound) in [
+            ("subscribed_after", value.subscribed_after, &mut filter.subscribed_after),
+            ("subscribed_before", value.subscribed_before, &mut filter.subscribed_before)
+        ] {
+            if let Some(value) = value {
+                match DateTime::parse_from_rfc3339(&value) {
+                    Ok(date) => *bound = Some(date.with_timezone(&Utc)),
+                    Err(_) => invalid_params.push(InvalidParam {
+                        name,
+                        code: "invalid_date",
+                        reason: format!("{} is not an RFC 3339 date.", value)
+                    })
+                }
+            }
+        }
+
+        if invalid_params.is_empty() {
+            Ok(filter)
+        } else {
+            Err(invalid_params)
+        }
+    }
+}
+
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct SubscriberSummary {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    // status of their membership, and when they joined the list
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>
+}
+
+impl SubscriberFilter {
+    /*
+        start a query over the subscribers matching the filter,
+        callers append the ordering and any further condition
+
+        `s` is the subscriber and `m` their membership, a list
+        that does not exist simply has no members
+     */
+    pub fn select_subscribers(&self) -> QueryBuilder<'static, Postgres> {
+        let mut query = QueryBuilder::<Postgres>::new(
+            "SELECT s.id, s.email, s.name, m.status, m.subscribed_at \
+            FROM subscriptions s \
+            JOIN list_memberships m ON m.subscriber_id = s.id \
+            WHERE m.list_id = (SELECT list_id FROM lists WHERE slug = "
+        );
+        // a constant list id, rather than a join, lets the planner seek
+        // `list_memberships_subscribed_at_idx` for the keyset pagination
+        query.push_bind(self.list.clone()).push(")");
+        if let Some(status) = &self.status {
+            query.push(" AND m.status = ").push_bind(status.clone());
+        }
+        if let Some(subscribed_after) = self.subscribed_after {
+            query.push(" AND m.subscribed_at >= ").push_bind(subscribed_after);
+        }
+        if let Some(subscribed_before) = self.subscribed_before {
+            query.push(" AND m.subscribed_at < ").push_bind(subscribed_before);
+        }
+        if let Some(search) = &self.search {
+            let pattern = format!("%{}%", escape_like_pattern(search));
+            query
+                .push(" AND (s.email ILIKE ")
+                .push_bind(pattern.clone())
+                .push(" OR s.name ILIKE ")
+                .push_bind(pattern)
+                .push(")");
+        }
+        query
+    }
+}
+
+// `%` and `_` typed by the admin are matched literally
+fn escape_like_pattern(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_like_pattern, FilterParameters, SubscriberFilter};
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like_pattern(r"50%_off\"), r"50\%\_off\\");
+    }
+
+    #[test]
+    fn every_invalid_parameter_is_reported() {
+        let parameters = FilterParameters {
+            list: None,
+            status: Some("gone".into()),
+            subscribed_after: Some("yesterday".into()),
+            subscribed_before: Some("tomorrow".into()),
+            search: None
+        };
+
+        let invalid_params = SubscriberFilter::try_from(parameters).unwrap_err();
+
+        let names: Vec<_> = invalid_params.iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["status", "subscribed_after", "subscribed_before"]);
+    }
+}
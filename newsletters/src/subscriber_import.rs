@@ -0,0 +1,309 @@
+use std::collections::HashSet;
+
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::confirmation_email_worker::enqueue_confirmation_emails;
+use crate::domain::NewSubscriber;
+use crate::domain_policy::DomainPolicy;
+use crate::routes::generate_subscription_token;
+
+// rows inserted with a single statement
+const BATCH_SIZE: usize = 1000;
+
+/// Status given to every imported subscriber.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    // the other tool already collected their consent
+    Confirmed,
+    // they will have to confirm before receiving any issue,
+    // a confirmation email is queued for each of them
+    PendingConfirmation
+}
+
+impl ImportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportStatus::Confirmed => "confirmed",
+            ImportStatus::PendingConfirmation => "pending_confirmation"
+        }
+    }
+}
+
+/// A row of the imported file, before validation.
+#[derive(serde::Deserialize, Debug)]
+pub struct ImportRow {
+    pub email: String,
+    pub name: String
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct ImportReport {
+    pub total_rows: usize,
+    pub imported: usize,
+    pub rejected: Vec<RejectedRow>
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct RejectedRow {
+    // 1-based, the CSV header does not count
+    pub row: usize,
+    pub email: Option<String>,
+    pub reasons: Vec<String>
+}
+
+/*
+    rows of a CSV file with (at least) an `email` and a `name` column
+
+    the file is rejected as a whole if the columns are missing,
+    a malformed row only rejects that row
+ */
+pub fn parse_csv(body: &[u8]) -> Result<Vec<Result<ImportRow, String>>, String> {
+    let mut reader = csv::Reader::from_reader(body);
+    let headers = reader.headers().map_err(|e| format!("invalid CSV header: {}", e))?;
+    for column in ["email", "name"] {
+        if !headers.iter().any(|h| h == column) {
+            return Err(format!("the CSV header has no '{}' column", column));
+        }
+    }
+
+    Ok(reader
+        .deserialize::<ImportRow>()
+        .map(|row| row.map_err(|e| e.to_string()))
+        .collect())
+}
+
+/*
+    rows of a JSON array of `{"email": ..., "name": ...}` objects
+
+    the file is rejected as a whole if it is not an array,
+    an invalid element only rejects that row
+ */
+pub fn parse_json(body: &[u8]) -> Result<Vec<Result<ImportRow, String>>, String> {
+    let values: Vec<serde_json::Value> = serde_json::from_slice(body)
+        .map_err(|e| format!("expected a JSON array of subscribers: {}", e))?;
+
+    Ok(values
+        .into_iter()
+        .map(|value| serde_json::from_value(value).map_err(|e| e.to_string()))
+        .collect())
+}
+
+/*
+    validate every row with the same rules as the signup form, domain
+    policy included, then add the valid ones to the list in batches
+
+    addresses we already know, from another list, are added to this
+    one as well: only the members of the list are rejected
+
+    the whole import runs in a single transaction: if it fails halfway,
+    nothing is imported, no confirmation email is queued, and the file
+    can be submitted again
+ */
+#[tracing::instrument(skip(pool, rows, domain_policy), fields(total_rows = rows.len()))]
+pub async fn import_subscribers(
+    pool: &PgPool,
+    rows: Vec<Result<ImportRow, String>>,
+    list_id: Uuid,
+    status: ImportStatus,
+    domain_policy: &DomainPolicy
+) -> Result<ImportReport, anyhow::Error> {
+    let mut report = ImportReport {
+        total_rows: rows.len(),
+        ..Default::default()
+    };
+
+    let mut valid_rows = Vec::with_capacity(rows.len());
+    for (i, row) in rows.into_iter().enumerate() {
+        let row_number = i + 1;
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                report.rejected.push(RejectedRow { row: row_number, email: None, reasons: vec![e] });
+                continue;
+            }
+        };
+        match NewSubscriber::parse(row.name, row.email.clone()) {
+            Ok(subscriber) => match domain_policy.check(&subscriber.email).await {
+                Ok(()) => valid_rows.push((row_number, subscriber)),
+                Err(e) => report.rejected.push(RejectedRow {
+                    row: row_number,
+                    email: Some(row.email),
+                    reasons: vec![format!("email: {}", e)]
+                })
+            },
+            Err(e) => report.rejected.push(RejectedRow {
+                row: row_number,
+                email: Some(row.email),
+                reasons: e
+                    .name
+                    .map(|e| format!("name: {}", e))
+                    .into_iter()
+                    .chain(e.email.map(|e| format!("email: {}", e)))
+                    .collect()
+            })
+        }
+    }
+
+    let mut transaction = pool.begin().await?;
+    for batch in valid_rows.chunks(BATCH_SIZE) {
+        let mut inserted = insert_batch(&mut transaction, batch, list_id, status).await?;
+        report.imported += inserted.len();
+        // whatever was not inserted clashed with an existing member,
+        // or with an earlier row of the same file
+        for (row_number, subscriber) in batch {
+            if !inserted.remove(subscriber.email.canonical()) {
+                report.rejected.push(RejectedRow {
+                    row: *row_number,
+                    email: Some(subscriber.email.to_string()),
+                    reasons: vec!["email: the address is already on the list.".into()]
+                });
+            }
+        }
+    }
+    transaction.commit().await?;
+
+    report.rejected.sort_by_key(|r| r.row);
+    Ok(report)
+}
+
+/*
+    two multi-row inserts per batch, fed with one array per column:
+    the unknown subscribers first, then the memberships
+
+    returns the canonical emails that were actually added to the list
+ */
+async fn insert_batch(
+    transaction: &mut Transaction<'_, Postgres>,
+    batch: &[(usize, NewSubscriber)],
+    list_id: Uuid,
+    status: ImportStatus
+) -> Result<HashSet<String>, sqlx::Error> {
+    let ids: Vec<Uuid> = batch.iter().map(|_| Uuid::new_v4()).collect();
+    let emails: Vec<&str> = batch.iter().map(|(_, s)| s.email.as_ref()).collect();
+    let canonical_emails: Vec<&str> = batch.iter().map(|(_, s)| s.email.canonical()).collect();
+    let names: Vec<&str> = batch.iter().map(|(_, s)| s.name.as_ref()).collect();
+    let now = Utc::now();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_canonical, name, subscribed_at)
+        SELECT id, email, email_canonical, name, $5
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[]) AS rows(id, email, email_canonical, name)
+        ON CONFLICT (email_canonical) DO NOTHING
+        "#,
+        &ids,
+        &emails as &[&str],
+        &canonical_emails as &[&str],
+        &names as &[&str],
+        now
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+    let inserted = sqlx::query!(
+        r#"
+        WITH inserted AS (
+            INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+            SELECT $1, id, $3, $4
+            FROM subscriptions
+            WHERE email_canonical = ANY($2)
+            ON CONFLICT (list_id, subscriber_id) DO NOTHING
+            RETURNING subscriber_id
+        )
+        SELECT s.id, s.email_canonical
+        FROM subscriptions s
+        JOIN inserted ON inserted.subscriber_id = s.id
+        "#,
+        list_id,
+        &canonical_emails as &[&str],
+        status.as_str(),
+        now
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    if status == ImportStatus::PendingConfirmation {
+        let subscriber_ids: Vec<Uuid> = inserted.iter().map(|r| r.id).collect();
+        request_confirmations(transaction, &subscriber_ids, list_id).await?;
+    }
+
+    Ok(inserted.into_iter().map(|r| r.email_canonical).collect())
+}
+
+/*
+    one confirmation token per new member, as the signup form would
+    issue; the emails are queued rather than sent, the delivery worker
+    picks them up once the import is committed
+ */
+async fn request_confirmations(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+    list_id: Uuid
+) -> Result<(), sqlx::Error> {
+    let tokens: Vec<String> = subscriber_ids.iter().map(|_| generate_subscription_token()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_tokens (subscription_token, subscriber_id, list_id)
+        SELECT token, subscriber_id, $3
+        FROM UNNEST($1::text[], $2::uuid[]) AS rows(token, subscriber_id)
+        "#,
+        &tokens,
+        subscriber_ids,
+        list_id
+        )
+        .execute(&mut *transaction)
+        .await?;
+    enqueue_confirmation_emails(transaction, &tokens).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_csv, parse_json};
+
+    #[test]
+    fn csv_rows_are_parsed_by_column_name() {
+        let body = "name,email,ignored\nUrsula,ursula@example.com,x\n";
+
+        let rows = parse_csv(body.as_bytes()).unwrap();
+
+        let row = rows[0].as_ref().unwrap();
+        assert_eq!(row.email, "ursula@example.com");
+        assert_eq!(row.name, "Ursula");
+    }
+
+    #[test]
+    fn a_csv_without_an_email_column_is_rejected() {
+        let body = "name,mail\nUrsula,ursula@example.com\n";
+
+        assert!(parse_csv(body.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn a_malformed_csv_row_only_rejects_that_row() {
+        let body = "email,name\nursula@example.com,Ursula\noctavia@example.com\n";
+
+        let rows = parse_csv(body.as_bytes()).unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].is_ok());
+        assert!(rows[1].is_err());
+    }
+
+    #[test]
+    fn an_invalid_json_element_only_rejects_that_row() {
+        let body = r#"[{"email": "ursula@example.com", "name": "Ursula"}, {"email": 42}]"#;
+
+        let rows = parse_json(body.as_bytes()).unwrap();
+
+        assert!(rows[0].is_ok());
+        assert!(rows[1].is_err());
+    }
+
+    #[test]
+    fn json_that_is_not_an_array_is_rejected() {
+        assert!(parse_json(br#"{"email": "ursula@example.com"}"#).is_err());
+    }
+}
@@ -3,6 +3,7 @@ use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry, fmt::MakeWriter};
 use tracing::subscriber::set_global_default;
+use tokio::task::JoinHandle;
 
 /// Compose multiple layers into a tracing's subscriber
 /// 
@@ -55,4 +56,17 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     // set_global_default can be used by applications to specify
     // what subscriber should be used to process spans.
     set_global_default(subscriber).expect("failed to set subscriber");      
-}
\ No newline at end of file
+}
+
+/// Run a CPU-intensive closure on tokio's blocking thread pool
+/// 
+/// The closure is executed in the same span as the caller, 
+/// so that its logs are still attached to the current request
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
@@ -0,0 +1,38 @@
+use actix_web::HttpResponse;
+use actix_web::http::header::LOCATION;
+
+/*
+    format an error together with the whole chain of its sources,
+    so that our logs capture the root cause and not just the top-level message
+ */
+pub fn error_chain_fmt(
+    e: &impl std::error::Error,
+    f: &mut std::fmt::Formatter<'_>
+) -> std::fmt::Result {
+    writeln!(f, "{}\n", e)?;
+    let mut current = e.source();
+    while let Some(cause) = current {
+        writeln!(f, "Caused by:\n\t{}", cause)?;
+        current = cause.source();
+    }
+    Ok(())
+}
+
+/*
+    redirect the browser to another page after a form submission
+ */
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+/*
+    return an opaque 500 while preserving the error's root cause for logging
+ */
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
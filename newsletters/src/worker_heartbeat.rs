@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// how often a running loop records that it is alive
+const BEAT_INTERVAL: Duration = Duration::from_secs(15);
+// the loops sleep 10s between two polls when idle: a few beats
+// may be missed before we consider a loop gone
+pub const MAX_HEARTBEAT_AGE: Duration = Duration::from_secs(60);
+
+/// Background loop a heartbeat comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerKind {
+    DeliveryWorker,
+    IssueScheduler
+}
+
+impl WorkerKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkerKind::DeliveryWorker => "delivery_worker",
+            WorkerKind::IssueScheduler => "issue_scheduler"
+        }
+    }
+}
+
+/*
+    heartbeat of a single background loop, identified by a random id
+    for as long as the process runs
+
+    `beat` is called on every iteration of the loop but only writes
+    to the database once per `BEAT_INTERVAL`
+ */
+pub struct Heartbeat {
+    worker_id: Uuid,
+    kind: WorkerKind,
+    started_at: DateTime<Utc>,
+    last_beat: Option<Instant>
+}
+
+impl Heartbeat {
+    pub fn new(kind: WorkerKind) -> Self {
+        Self {
+            worker_id: Uuid::new_v4(),
+            kind,
+            started_at: Utc::now(),
+            last_beat: None
+        }
+    }
+
+    pub async fn beat(&mut self, pool: &PgPool) {
+        if self.last_beat.is_some_and(|last_beat| last_beat.elapsed() < BEAT_INTERVAL) {
+            return;
+        }
+        // a failed beat is retried on the next iteration, it must
+        // not stop the loop from doing its actual job
+        match record_heartbeat(pool, self.worker_id, self.kind, self.started_at).await {
+            Ok(()) => self.last_beat = Some(Instant::now()),
+            Err(e) => tracing::warn!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                worker_kind = self.kind.as_str(),
+                "failed to record the worker heartbeat"
+            )
+        }
+    }
+}
+
+#[tracing::instrument(skip(pool), level = "debug")]
+async fn record_heartbeat(
+    pool: &PgPool,
+    worker_id: Uuid,
+    kind: WorkerKind,
+    started_at: DateTime<Utc>
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO worker_heartbeats (worker_id, kind, started_at, last_beat_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (worker_id) DO UPDATE
+        SET last_beat_at = now()
+        "#,
+        worker_id,
+        kind.as_str(),
+        started_at
+        )
+        .execute(pool)
+        .await?;
+    // every restart gets a new id, the rows of stopped processes are dropped after a while
+    sqlx::query!(
+        r#"
+        DELETE FROM worker_heartbeats
+        WHERE last_beat_at < now() - interval '1 day'
+        "#
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/*
+    how long ago the most recent heartbeat of this kind was recorded,
+    `None` if no loop of this kind ever ran against our database
+ */
+pub async fn last_heartbeat_age(pool: &PgPool, kind: WorkerKind) -> Result<Option<Duration>, sqlx::Error> {
+    let age_ms = sqlx::query_scalar!(
+        r#"
+        SELECT (EXTRACT(EPOCH FROM now() - max(last_beat_at)) * 1000)::bigint as age_ms
+        FROM worker_heartbeats
+        WHERE kind = $1
+        "#,
+        kind.as_str()
+        )
+        .fetch_one(pool)
+        .await?;
+
+    // clocks never run backwards, unless the row was written by another host
+    Ok(age_ms.map(|age_ms| Duration::from_millis(age_ms.max(0) as u64)))
+}
@@ -1,11 +1,14 @@
 //! tests/health_check.rs
 
-use std::{net::TcpListener};
+use std::{net::TcpListener, time::Duration};
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use sqlx::{PgConnection, Connection, PgPool, Executor};
 
+use secrecy::Secret;
 use uuid::Uuid;
-use zero2prod::{configuration::{get_configuration, DatabaseSettings}, telemetry::{get_subscriber, init_subscriber}};
+use wiremock::{Mock, MockServer, ResponseTemplate, matchers::{any, method, path}};
+use zero2prod::{authentication::create_admin, configuration::{get_configuration, DatabaseSettings, EmailTransportSettings, Settings, WorkerSettings}, domain_policy::DomainPolicyMode, domain::{FormToken, UnsubscribeToken}, confirmation_email_worker::try_send_confirmation_email, email_client::EmailClient, issue_delivery_worker::{try_execute_task, ExecutionOutcome}, issue_scheduler::{try_enqueue_due_issue, SchedulerOutcome}, startup::{ApplicationBaseUrl, HmacSecret}, telemetry::{get_subscriber, init_subscriber}};
 // `tokio::test` is the testing equivalent of `tokio::main`.
 // It also spares you from having to specify the `#[test]` attribute.
 //
@@ -35,32 +38,352 @@ async fn health_check_works() {
 }
 
 
+#[tokio::test]
+async fn liveness_probe_always_answers_200() {
+    let app = spawn_app().await;
+
+    let response = app.get_health("live").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+}
+
+#[tokio::test]
+async fn readiness_probe_reports_every_component_up() {
+    let app = spawn_app().await;
+    app.record_worker_heartbeats().await;
+
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Cache-Control"], "no-store");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+    for component in ["database", "migrations", "email", "delivery_worker", "issue_scheduler"] {
+        assert_eq!(body["components"][component]["status"], "up", "{}", component);
+        assert!(body["components"][component]["latency_ms"].is_u64(), "{}", component);
+        assert!(body["components"][component].get("detail").is_none(), "{}", component);
+    }
+}
+
+#[tokio::test]
+async fn readiness_probe_is_degraded_without_worker_heartbeats() {
+    let app = spawn_app().await;
+
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["components"]["database"]["status"], "up");
+    assert_eq!(body["components"]["delivery_worker"]["status"], "down");
+    assert_eq!(body["components"]["delivery_worker"]["detail"], "no heartbeat was ever recorded");
+    assert_eq!(body["components"]["issue_scheduler"]["status"], "down");
+}
+
+#[tokio::test]
+async fn readiness_probe_ignores_stale_worker_heartbeats() {
+    let app = spawn_app().await;
+    app.record_worker_heartbeats().await;
+    sqlx::query!("UPDATE worker_heartbeats SET last_beat_at = now() - interval '10 minutes' WHERE kind = 'delivery_worker'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_health("ready").await;
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["components"]["delivery_worker"]["status"], "down");
+    assert!(body["components"]["delivery_worker"]["detail"].as_str().unwrap().starts_with("last heartbeat 600s ago"));
+    assert_eq!(body["components"]["issue_scheduler"]["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_probe_is_degraded_if_the_email_provider_is_unreachable() {
+    // bound then dropped, nobody is listening anymore
+    let closed_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let app = spawn_app_with_configuration(|c| {
+        set_email_api_url(c, format!("http://127.0.0.1:{}", closed_port));
+    })
+    .await;
+    app.record_worker_heartbeats().await;
+
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["components"]["email"]["status"], "down");
+    assert_eq!(body["components"]["database"]["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_probe_does_not_leak_error_messages() {
+    let app = spawn_app().await;
+    app.record_worker_heartbeats().await;
+    sqlx::query("ALTER TABLE worker_heartbeats RENAME TO worker_heartbeats_secret_name")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_health("ready").await;
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["components"]["delivery_worker"]["status"], "down");
+    assert_eq!(body["components"]["delivery_worker"]["detail"], "the check failed");
+    assert!(!body.to_string().contains("secret_name"), "{}", body);
+}
+
+#[tokio::test]
+async fn readiness_probe_reuses_the_outcome_of_the_email_check() {
+    let app = spawn_app().await;
+    app.record_worker_heartbeats().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..3 {
+        let body: serde_json::Value = app.get_health("ready").await.json().await.unwrap();
+        assert_eq!(body["components"]["email"]["status"], "up");
+    }
+}
+
+#[tokio::test]
+async fn readiness_probe_answers_503_with_pending_migrations() {
+    let app = spawn_app().await;
+    app.record_worker_heartbeats().await;
+    let latest_version: i64 = sqlx::query_scalar("SELECT max(version) FROM _sqlx_migrations")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query("DELETE FROM _sqlx_migrations WHERE version = $1")
+        .bind(latest_version)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_health("ready").await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["components"]["migrations"]["status"], "down");
+    assert_eq!(body["components"]["migrations"]["detail"], "some migrations are not applied");
+}
+
+#[tokio::test]
+async fn metrics_count_requests_by_route_pattern_and_status() {
+    let app = spawn_app().await;
+    app.api_client.get(format!("{}/health_check", &app.address)).send().await.unwrap();
+    app.api_client.get(format!("{}/admin/subscribers/{}", &app.address, Uuid::new_v4())).send().await.unwrap();
+    app.api_client.get(format!("{}/no-such-page/{}", &app.address, Uuid::new_v4())).send().await.unwrap();
+
+    let response = app.get_metrics().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"].to_str().unwrap().starts_with("text/plain"));
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"http_requests_total{method="GET",route="/health_check",status="200"}"#));
+    assert!(body.contains(r#"http_request_duration_seconds_bucket{method="GET",route="/health_check",status="200",le="0.005"}"#));
+    // anonymous users are sent to the login page
+    assert!(body.contains(r#"http_requests_total{method="GET",route="/admin/subscribers/{subscriber_id}",status="303"}"#));
+    assert!(body.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"}"#));
+    assert!(!body.contains("no-such-page"));
+    assert!(body.contains(r#"db_pool_connections{pool="api",state="idle"}"#));
+}
+
+#[tokio::test]
+async fn metrics_count_subscription_outcomes_and_email_results() {
+    let app = spawn_app().await;
+    let accepted = r#"subscriptions_total{outcome="accepted"}"#;
+    let validation_failed = r#"subscriptions_total{outcome="validation_failed"}"#;
+    let duplicate = r#"subscriptions_total{outcome="duplicate"}"#;
+    let email_error = r#"subscriptions_total{outcome="email_error"}"#;
+    let emails_sent = r#"emails_sent_total{result="success",transport="http"}"#;
+    let emails_failed = r#"emails_sent_total{result="failure",transport="http"}"#;
+    let before = [accepted, validation_failed, duplicate, email_error, emails_sent, emails_failed];
+    let mut values_before = Vec::new();
+    for series in before {
+        values_before.push(app.metric_value(series).await);
+    }
+
+    // accepted, then duplicate once confirmed
+    create_confirmed_subscriber(&app).await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+    app.post_subscriptions("name=&email=ursula_le_guin%40gmail.com".into()).await;
+    let _mock_guard = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions("name=chenlog&email=loc.tranbao%40outlook.com".into()).await;
+
+    for (series, before) in before.iter().zip(values_before) {
+        assert!(app.metric_value(series).await >= before + 1.0, "{}", series);
+    }
+}
+
+#[tokio::test]
+async fn metrics_are_only_served_on_the_admin_port() {
+    let app = spawn_app().await;
+
+    let public = app.api_client.get(format!("{}/metrics", &app.address)).send().await.unwrap();
+    let admin = app.get_metrics().await;
+
+    assert_eq!(public.status().as_u16(), 404);
+    assert_eq!(admin.status().as_u16(), 200);
+    assert!(admin.text().await.unwrap().contains("db_pool_connections"));
+}
+
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
     // Arrange
     let app = spawn_app().await;
 
-    // Act
-    let client = reqwest::Client::new();
     let body = "name=chenlog&email=loc.tranbao%40outlook.com";
-    let response = client
-                        .post(format!("{}/subscriptions", &app.address))
-                        .header("Content-Type", "application/x-www-form-urlencoded")
-                        .body(body)
-                        .send()
-                        .await
-                        .expect("failed to execute request");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
 
     // Assert
     assert_eq!(200, response.status().as_u16());
-    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.email, s.name, m.status
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        "#
+        )
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "loc.tranbao@outlook.com");
     assert_eq!(saved.name, "chenlog");
+    assert_eq!(saved.status, "pending_confirmation");
+
+}
+
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_with_a_link() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=chenlog&email=loc.tranbao%40outlook.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // the two links should be identical
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
+
+#[tokio::test]
+async fn subscribe_fails_if_the_confirmation_email_cannot_be_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=chenlog&email=loc.tranbao%40outlook.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(500, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn confirmations_without_token_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/subscriptions/confirm", app.address))
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+            "{}/subscriptions/confirm?subscription_token=notarealtoken",
+            app.address
+        ))
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=chenlog&email=loc.tranbao%40outlook.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Act
+    let response = reqwest::get(confirmation_links.html)
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.email, s.name, m.status
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        "#
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
 
+    assert_eq!(saved.email, "loc.tranbao@outlook.com");
+    assert_eq!(saved.name, "chenlog");
+    assert_eq!(saved.status, "confirmed");
 }
 
 #[tokio::test]
@@ -108,7 +431,7 @@ async fn subscribe_returns_a_200_when_fields_are_present_but_empty() {
     for (body, description) in test_cases {
         // Act
         let response = client
-            .post(&format!("{}/subscriptions", &app.address))
+            .post(format!("{}/subscriptions", &app.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -125,58 +448,3369 @@ async fn subscribe_returns_a_200_when_fields_are_present_but_empty() {
     }
 }
 
-//  Ensure that the tracing stack is only initlalised once using once_cell
-static TRACING: Lazy<()> = Lazy::new(|| {
-    let default_filter_level = "info".to_string();
-    let subscriber_name = "test".to_string();
-
-    // We cannot assign the output of get_subscriber to a variable based on the value of TEST_LOG
-    // because the sink is part of the type returned by get_subscriber, therefore they are not the
-    // same type, We could work around it, but this is the most straight-forward way of moving forward.
+#[tokio::test]
+async fn subscribe_returns_a_problem_details_body_for_invalid_fields() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        ("name=&email=ursula_le_guin%40gmail.com", "name", "empty"),
+        ("name=Ursula&email=", "email", "empty"),
+        ("name=Ursula&email=definitely-not-an-email", "email", "invalid_syntax"),
+        ("name=Ursula%20%7BLe%20Guin%7D&email=ursula_le_guin%40gmail.com", "name", "forbidden_character"),
+    ];
+    for (body, field, code) in test_cases {
+        // Act
+        let response = app.post_subscriptions(body.into()).await;
 
-    if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
-        init_subscriber(subscriber);
-    } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
-        init_subscriber(subscriber);
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "application/problem+json",
+            "unexpected content type for {}",
+            body
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(problem["status"], 400);
+        let invalid_params = problem["invalid-params"].as_array().unwrap();
+        assert_eq!(invalid_params.len(), 1, "{}", problem);
+        assert_eq!(invalid_params[0]["name"], field);
+        assert_eq!(invalid_params[0]["code"], code);
     }
-});
+}
 
-pub struct TestApp {
-    pub address: String,
-    pub db_pool: PgPool
+#[tokio::test]
+async fn subscribe_reports_every_invalid_field_at_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let name = "a".repeat(257);
+
+    // Act
+    let response = app.post_subscriptions(format!("name={}&email=ursula", name)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        problem["invalid-params"],
+        serde_json::json!([
+            {
+                "name": "name",
+                "code": "too_long",
+                "reason": "the name cannot be longer than 256 characters."
+            },
+            {
+                "name": "email",
+                "code": "invalid_syntax",
+                "reason": "ursula is not a valid email address."
+            }
+        ])
+    );
 }
 
-// No .await call, therefore no need for `spawn_app` to be async now.
-// We are also running tests, so it is not worth it to propagate errors:
-// if we fail to perform the required setup we can just panic and crash
-// all the things.
-async fn spawn_app() -> TestApp {
-    //  The first time initialize is invoked the code in TRACING is executed
-    //  All other invocation will instead skip execution
-    Lazy::force(&TRACING);
+#[tokio::test]
+async fn the_forbidden_character_is_named_in_the_error() {
+    // Arrange
+    let app = spawn_app().await;
 
-    let listener = TcpListener::bind("127.0.0.1:0")
-    .expect("Failed to bind random port");
+    // Act
+    let response = app
+        .post_subscriptions("name=Ursula%20%3CLe%20Guin%3E&email=ursula_le_guin%40gmail.com".into())
+        .await;
 
-    let mut configuration = get_configuration().expect("failed to read configuration");
-    configuration.database.database_name = Uuid::new_v4().to_string();
+    // Assert
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["invalid-params"][0]["reason"], "the name cannot contain '<'.");
+}
 
-    // the 'Connection' trait MUST be in scope for us to invoke
-    // 'PgConnection::connect' - it is not an inherent method of the struct!
-    let connection_pool = configure_database(&configuration.database).await;
+#[tokio::test]
+async fn subscribe_accepts_json_bodies() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
 
-    // We retrieve the port assigned to us by the OS
-    let port = listener.local_addr().unwrap().port();
-    let server = zero2prod::startup::run(listener, connection_pool.clone()).expect("Failed to bind address");
-    let address = format!("http://127.0.0.1:{}", port);
-    let _ = tokio::spawn(server);
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com"
+        }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch saved subscription");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+}
+
+#[tokio::test]
+async fn subscribe_validates_json_bodies_like_forms() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .json(&serde_json::json!({ "name": "", "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["invalid-params"][0]["name"], "name");
+}
+
+#[tokio::test]
+async fn subscribe_returns_json_when_the_client_accepts_it() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Accept", "application/json")
+        .json(&serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "application/json");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["message"].is_string());
+}
+
+#[tokio::test]
+async fn subscribe_rejects_unsupported_media_types_with_a_415() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        Some("text/plain"),
+        Some("application/xml"),
+        Some("multipart/form-data; boundary=xyz"),
+        None
+    ];
+
+    for content_type in test_cases {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/subscriptions", &app.address))
+            .body("name=le%20guin&email=ursula_le_guin%40gmail.com");
+        if let Some(content_type) = content_type {
+            request = request.header("Content-Type", content_type);
+        }
+
+        // Act
+        let response = request.send().await.expect("failed to execute request");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 415, "{:?}", content_type);
+        assert_eq!(response.headers()["Content-Type"], "application/problem+json");
+    }
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_malformed_json() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/json")
+        .body(r#"{"name": "le guin""#)
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(response.headers()["Content-Type"], "application/problem+json");
+}
+
+#[tokio::test]
+async fn subscribing_too_often_is_rejected_with_a_429() {
+    // Arrange
+    let app = spawn_app_with_configuration(|c| {
+        c.rate_limiting.routes[0].capacity = 2;
+        c.rate_limiting.routes[0].refill_per_minute = 1;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com".to_string();
+
+    // Act
+    let first = app.post_subscriptions(body.clone()).await;
+    let second = app.post_subscriptions(body.clone()).await;
+    let third = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(third.status().as_u16(), 429);
+    let retry_after: u64 = third.headers()["Retry-After"].to_str().unwrap().parse().unwrap();
+    assert!((1..=60).contains(&retry_after));
+    assert_eq!(third.headers()["Content-Type"], "application/problem+json");
+    // other routes are not throttled
+    let health_check = reqwest::get(format!("{}/health_check", &app.address)).await.unwrap();
+    assert_eq!(health_check.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn clients_behind_a_trusted_proxy_are_limited_separately() {
+    // Arrange
+    let app = spawn_app_with_configuration(|c| {
+        c.rate_limiting.routes[0].capacity = 1;
+        c.rate_limiting.trusted_proxies = vec!["127.0.0.1".to_string().try_into().unwrap()];
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let subscribe_from = |client: &'static str| {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", client)
+            .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+            .send()
+    };
+
+    // Act
+    let first_client = subscribe_from("203.0.113.1").await.unwrap();
+    let second_client = subscribe_from("203.0.113.2").await.unwrap();
+    let first_client_again = subscribe_from("203.0.113.1").await.unwrap();
+
+    // Assert
+    assert_eq!(first_client.status().as_u16(), 200);
+    assert_eq!(second_client.status().as_u16(), 200);
+    assert_eq!(first_client_again.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn forwarded_addresses_from_untrusted_peers_are_ignored() {
+    // Arrange
+    let app = spawn_app_with_configuration(|c| {
+        c.rate_limiting.routes[0].capacity = 1;
+        c.rate_limiting.trusted_proxies = Vec::new();
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let subscribe_from = |client: &'static str| {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", client)
+            .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+            .send()
+    };
+
+    // Act
+    let first = subscribe_from("203.0.113.1").await.unwrap();
+    let spoofed = subscribe_from("203.0.113.2").await.unwrap();
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(spoofed.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn signups_filling_the_honeypot_look_successful_but_are_dropped() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&website=https%3A%2F%2Fspam.example";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn signups_submitted_too_fast_look_successful_but_are_dropped() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let form_token: serde_json::Value = reqwest::get(format!("{}/subscriptions/form-token", &app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let form_token = form_token["form_token"].as_str().unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=&form_token={}",
+            form_token
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn signups_with_an_old_enough_form_token_are_accepted() {
+    // Arrange
+    let app = spawn_app_with_configuration(|c| c.signup_protection.require_form_token = true).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let form_token = FormToken::generate(
+        chrono::Utc::now() - chrono::Duration::seconds(30),
+        &app.hmac_secret.0
+    );
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=&form_token={}",
+            form_token.as_ref()
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.get_subscriber_status().await, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn signups_without_a_form_token_are_dropped_when_it_is_required() {
+    // Arrange
+    let app = spawn_app_with_configuration(|c| c.signup_protection.require_form_token = true).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn addresses_differing_only_by_case_are_the_same_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=alice&email=alice%40example.com".into()).await;
+    let response = app
+        .post_subscriptions("name=alice&email=%20Alice%40Example.COM%20".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email, email_canonical FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    // the address is displayed as it was typed the first time
+    assert_eq!(saved[0].email, "alice@example.com");
+    assert_eq!(saved[0].email_canonical, "alice@example.com");
+}
+
+#[tokio::test]
+async fn addresses_are_stored_normalized() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=alice&email=Alice%40B%C3%BCcher.Example".into()).await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT email, email_canonical FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "Alice@xn--bcher-kva.example");
+    assert_eq!(saved.email_canonical, "alice@xn--bcher-kva.example");
+}
+
+#[tokio::test]
+async fn disposable_addresses_are_rejected_with_their_own_error_code() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40mailinator.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["invalid-params"][0]["name"], "email");
+    assert_eq!(problem["invalid-params"][0]["code"], "disposable_domain");
+}
+
+#[tokio::test]
+async fn only_allowed_domains_can_subscribe_in_allowlist_mode() {
+    // Arrange
+    let app = spawn_app_with_configuration(|c| {
+        c.domain_policy.mode = DomainPolicyMode::Allowlist;
+        c.domain_policy.domains = vec!["corp.example".into()];
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let allowed = app
+        .post_subscriptions("name=le%20guin&email=ursula%40corp.example".into())
+        .await;
+    let denied = app
+        .post_subscriptions("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(allowed.status().as_u16(), 200);
+    assert_eq!(denied.status().as_u16(), 400);
+    let problem: serde_json::Value = denied.json().await.unwrap();
+    assert_eq!(problem["invalid-params"][0]["code"], "domain_not_allowed");
+}
+
+#[tokio::test]
+async fn the_denied_domains_are_reloaded_when_their_file_changes() {
+    // Arrange
+    let domains_path = std::env::temp_dir().join(format!("denied-domains-{}.txt", Uuid::new_v4()));
+    std::fs::write(&domains_path, "").unwrap();
+    let app = spawn_app_with_configuration(|c| {
+        c.domain_policy.domains_path = Some(domains_path.to_string_lossy().into_owned());
+        c.domain_policy.reload_interval_seconds = 0;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let before = app
+        .post_subscriptions("name=le%20guin&email=ursula%40spam.example".into())
+        .await;
+
+    // Act
+    std::fs::write(&domains_path, "# spam, spam, spam\nspam.example\n").unwrap();
+    let after = app
+        .post_subscriptions("name=le%20guin&email=octavia%40spam.example".into())
+        .await;
+
+    // Assert
+    assert_eq!(before.status().as_u16(), 200);
+    assert_eq!(after.status().as_u16(), 400);
+    std::fs::remove_file(domains_path).unwrap();
+}
+
+#[tokio::test]
+async fn likely_typos_in_the_domain_are_answered_with_a_suggestion() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmial.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["invalid-params"][0]["code"], "possible_typo");
+    assert_eq!(problem["invalid-params"][0]["reason"], "did you mean ursula@gmail.com?");
+    assert_eq!(problem["suggestion"], "ursula@gmail.com");
+}
+
+#[tokio::test]
+async fn browsers_are_shown_the_suggestion_on_a_page() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")
+        .body("name=le%20guin&email=ursula%40outlok.com&list=newsletter")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.headers()["Content-Type"].to_str().unwrap().starts_with("text/html"));
+    let page = response.text().await.unwrap();
+    assert!(page.contains("Did you mean <strong>ursula@outlook.com</strong>?"));
+    assert!(page.contains(r#"name="keep_email_as_typed" value="true""#));
+    assert!(page.contains(r#"name="list" value="newsletter""#));
+}
+
+#[tokio::test]
+async fn addresses_can_be_kept_as_typed_despite_a_suggestion() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmial.com&keep_email_as_typed=true".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula@gmial.com");
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let duplicate = r#"subscriptions_total{outcome="duplicate"}"#;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first = app.post_subscriptions(body.into()).await;
+    let duplicates_before = app.metric_value(duplicate).await;
+    let second = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    // the counters are shared with the other tests, they can only go up
+    assert!(app.metric_value(duplicate).await >= duplicates_before + 1.0);
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(app.get_subscriber_status().await, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_a_confirmed_address_returns_a_neutral_success() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let _mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let duplicate = r#"subscriptions_total{outcome="duplicate"}"#;
+    let duplicates_before = app.metric_value(duplicate).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Accept", "application/json")
+        .json(&serde_json::json!({ "name": "le guin", "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["message"], "Check your inbox to confirm your subscription.");
+    assert_eq!(app.get_subscriber_status().await, "confirmed");
+    assert!(app.metric_value(duplicate).await >= duplicates_before + 1.0);
+}
+
+#[tokio::test]
+async fn unsubscribed_addresses_can_opt_back_in() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = app.publish_and_get_unsubscribe_link().await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act - Part 1 - Sign up again
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    assert_eq!(app.get_subscriber_status().await, "pending_confirmation");
+
+    // Act - Part 2 - Confirm again
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(app.get_subscriber_status().await, "confirmed");
+}
+
+#[tokio::test]
+async fn an_old_confirmation_link_does_not_resubscribe_an_unsubscribed_address() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let unsubscribe_link = app.publish_and_get_unsubscribe_link().await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(app.get_subscriber_status().await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn an_old_confirmation_link_does_not_confirm_an_address_opting_back_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let old_confirmation_links = create_unconfirmed_subscriber(&app).await;
+    reqwest::get(old_confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let unsubscribe_link = app.publish_and_get_unsubscribe_link().await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    create_unconfirmed_subscriber(&app).await;
+
+    // Act
+    let response = reqwest::get(old_confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(app.get_subscriber_status().await, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        // we assert that no request is fired at the email API
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // the mock verifies on drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_skip_subscribers_with_invalid_stored_emails() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // a row that no longer passes our validation rules
+    app.insert_subscriber("not-an-email", "legacy", "confirmed", chrono::Utc::now()).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        // Act
+        let response = app.post_newsletters(invalid_body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    // random credentials
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    // random password
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn an_error_message_is_shown_after_a_failed_login() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Try to login
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed.</i></p>"));
+
+    // Act - Part 3 - Reload the login page
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed."));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    let response = app.login_as_test_user().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Follow the redirect
+    let html_page = app.get_admin_dashboard().await.text().await.unwrap();
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin_dashboard().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - Login
+    let response = app.login_as_test_user().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 2 - Logout
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 3 - Attempt to load admin panel
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_session_of_a_deleted_user_is_anonymous() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    // the encoding the migration removing the seeded admin relies on
+    let stored_user_id = sqlx::query_scalar!(r#"SELECT state->>'user_id' as "user_id!" FROM sessions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored_user_id, format!("\"{}\"", app.test_user.user_id));
+    sqlx::query!("DELETE FROM users WHERE user_id = $1", app.test_user.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.get_admin_subscribers("").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let n_sessions = sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM sessions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_sessions, 0);
+}
+
+#[tokio::test]
+async fn the_session_id_is_rotated_on_login() {
+    // Arrange
+    let app = spawn_app().await;
+    let session_cookie = |response: &reqwest::Response| {
+        response
+            .cookies()
+            .find(|c| c.name() == "id")
+            .map(|c| c.value().to_owned())
+            .expect("no session cookie was set")
+    };
+    let first_session = session_cookie(&app.login_as_test_user().await);
+
+    // Act
+    let second_session = session_cookie(&app.login_as_test_user().await);
+
+    // Assert
+    assert_ne!(first_session, second_session);
+    // the previous session key can no longer be used
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}/admin/dashboard", &app.address))
+        .header("Cookie", format!("id={}", first_session))
+        .send()
+        .await
+        .expect("failed to execute request");
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logged_in_users_can_publish_from_the_admin_scope() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_as_test_user().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.api_client
+        .post(format!("{}/admin/newsletters", &app.address))
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_list_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_admin_subscribers("").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_subscriber_listing_walks_every_subscriber_exactly_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let subscribed_at = chrono::Utc::now();
+    let mut expected = Vec::new();
+    for i in 0..7 {
+        // several subscribers share the same timestamp, the id breaks the tie
+        let at = subscribed_at + chrono::Duration::seconds(i / 2);
+        let email = format!("subscriber{}@example.com", i);
+        app.insert_subscriber(&email, "name", "confirmed", at).await;
+        expected.push(email);
+    }
+
+    // Act
+    let mut seen = Vec::new();
+    let mut n_pages = 0;
+    let mut query = "limit=3".to_string();
+    loop {
+        let response = app.get_admin_subscribers(&query).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let page: serde_json::Value = response.json().await.unwrap();
+        n_pages += 1;
+        for subscriber in page["subscribers"].as_array().unwrap() {
+            seen.push(subscriber["email"].as_str().unwrap().to_string());
+        }
+        match page["next_cursor"].as_str() {
+            Some(cursor) => query = format!("limit=3&cursor={}", cursor),
+            None => break
+        }
+    }
+
+    // Assert
+    assert_eq!(n_pages, 3);
+    let mut sorted_seen = seen.clone();
+    sorted_seen.sort();
+    assert_eq!(sorted_seen, expected);
+    // the emails of subscribers sharing a timestamp are not ordered,
+    // but the timestamps are
+    assert_eq!(&seen[6], "subscriber6@example.com");
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let now = chrono::Utc::now();
+    let day = chrono::Duration::days(1);
+    app.insert_subscriber("ursula@example.com", "Ursula Le Guin", "confirmed", now - day * 10).await;
+    app.insert_subscriber("octavia@example.com", "Octavia Butler", "pending_confirmation", now - day * 5).await;
+    app.insert_subscriber("iain@example.com", "Iain M. Banks", "unsubscribed", now - day).await;
+    app.insert_subscriber("n_k@example.com", "N. K. Jemisin", "confirmed", now).await;
+    let date = |d: chrono::DateTime<chrono::Utc>| {
+        d.to_rfc3339_opts(chrono::SecondsFormat::Secs, true).replace('+', "%2B")
+    };
+    let test_cases = vec![
+        ("status=confirmed".to_string(), vec!["ursula@example.com", "n_k@example.com"]),
+        ("status=unsubscribed".to_string(), vec!["iain@example.com"]),
+        (
+            format!("subscribed_after={}&subscribed_before={}", date(now - day * 6), date(now - day / 2)),
+            vec!["octavia@example.com", "iain@example.com"]
+        ),
+        ("search=BUTLER".to_string(), vec!["octavia@example.com"]),
+        ("search=Le%20Guin".to_string(), vec!["ursula@example.com"]),
+        // `_` is matched literally, not as a wildcard
+        ("search=n_k".to_string(), vec!["n_k@example.com"]),
+        ("status=confirmed&search=example".to_string(), vec!["ursula@example.com", "n_k@example.com"]),
+    ];
+
+    for (query, expected) in test_cases {
+        // Act
+        let page: serde_json::Value = app.get_admin_subscribers(&query).await.json().await.unwrap();
+
+        // Assert
+        let emails: Vec<_> = page["subscribers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["email"].as_str().unwrap())
+            .collect();
+        assert_eq!(emails, expected, "unexpected subscribers for {}", query);
+    }
+}
+
+#[tokio::test]
+async fn invalid_listing_parameters_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    // Act
+    let response = app
+        .get_admin_subscribers("status=gone&subscribed_after=yesterday&limit=0&cursor=garbage")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(response.headers()["Content-Type"], "application/problem+json");
+    let problem: serde_json::Value = response.json().await.unwrap();
+    let names: Vec<_> = problem["invalid-params"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["status", "subscribed_after", "limit", "cursor"]);
+}
+
+#[tokio::test]
+async fn subscriber_details_are_returned_by_id() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let subscriber_id = app
+        .insert_subscriber("ursula@example.com", "Ursula Le Guin", "confirmed", chrono::Utc::now())
+        .await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers/{}", &app.address, subscriber_id))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let details: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(details["id"], subscriber_id.to_string());
+    assert_eq!(details["email"], "ursula@example.com");
+    assert_eq!(details["name"], "Ursula Le Guin");
+    assert_eq!(details["lists"][0]["list"], "newsletter");
+    assert_eq!(details["lists"][0]["status"], "confirmed");
+    assert_eq!(details["deliveries"]["delivered"], 0);
+}
+
+#[tokio::test]
+async fn unknown_subscribers_are_reported_with_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/subscribers/{}", &app.address, Uuid::new_v4()))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_subscribers_export("").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_export_is_a_csv_of_the_matching_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let now = chrono::Utc::now();
+    let ursula_id = app
+        .insert_subscriber("ursula@example.com", "Le Guin, Ursula", "confirmed", now)
+        .await;
+    app.insert_subscriber("octavia@example.com", "Octavia Butler", "unsubscribed", now).await;
+
+    // Act
+    let response = app.get_subscribers_export("status=confirmed").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "text/csv; charset=utf-8");
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        r#"attachment; filename="subscribers.csv""#
+    );
+    let body = response.text().await.unwrap();
+    let mut reader = csv::Reader::from_reader(body.as_bytes());
+    let headers = reader.headers().unwrap().clone();
+    assert_eq!(headers, vec!["id", "email", "name", "status", "subscribed_at"]);
+    let records: Vec<_> = reader.records().map(|r| r.unwrap()).collect();
+    assert_eq!(records.len(), 1);
+    assert_eq!(&records[0][0], ursula_id.to_string());
+    assert_eq!(&records[0][1], "ursula@example.com");
+    // values containing the separator are quoted
+    assert_eq!(&records[0][2], "Le Guin, Ursula");
+    assert_eq!(&records[0][3], "confirmed");
+}
+
+#[tokio::test]
+async fn large_exports_are_streamed_in_full() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let n_subscribers = 5000;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_canonical, name, subscribed_at)
+        SELECT gen_random_uuid(), 'subscriber' || i || '@example.com', 'subscriber' || i || '@example.com', 'name', now()
+        FROM generate_series(1, $1) AS i
+        "#,
+        n_subscribers
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+        SELECT l.list_id, s.id, 'confirmed', s.subscribed_at
+        FROM subscriptions s, lists l
+        WHERE l.slug = 'newsletter'
+        "#
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.get_subscribers_export("").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    // header included
+    assert_eq!(body.lines().count(), n_subscribers as usize + 1);
+}
+
+#[tokio::test]
+async fn exports_with_invalid_filters_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    // Act
+    let response = app.get_subscribers_export("subscribed_before=tomorrow").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["invalid-params"][0]["name"], "subscribed_before");
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed", "text/csv", "email,name\n")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_csv_import_stores_valid_rows_and_reports_invalid_ones() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let body = "email,name\n\
+        ursula@example.com,Ursula Le Guin\n\
+        not-an-email,Octavia Butler\n\
+        nk@example.com,\n\
+        ted@example.com,Ted Chiang\n";
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed", "text/csv", body)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["total_rows"], 4);
+    assert_eq!(report["imported"], 2);
+    let rejected = report["rejected"].as_array().unwrap();
+    assert_eq!(rejected.len(), 2);
+    assert_eq!(rejected[0]["row"], 2);
+    assert_eq!(rejected[0]["email"], "not-an-email");
+    assert!(rejected[0]["reasons"][0].as_str().unwrap().starts_with("email:"));
+    assert_eq!(rejected[1]["row"], 3);
+    assert!(rejected[1]["reasons"][0].as_str().unwrap().starts_with("name:"));
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT s.email, m.status
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        ORDER BY s.email
+        "#
+        )
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let saved: Vec<_> = saved.iter().map(|r| (r.email.as_str(), r.status.as_str())).collect();
+    assert_eq!(
+        saved,
+        vec![("ted@example.com", "confirmed"), ("ursula@example.com", "confirmed")]
+    );
+}
+
+#[tokio::test]
+async fn an_import_rejects_the_domains_the_signup_form_rejects() {
+    // Arrange
+    let app = spawn_app_with_configuration(|c| c.domain_policy.domains = vec!["spam.example".into()]).await;
+    app.login_as_test_user().await;
+    let body = "email,name\n\
+        ursula@example.com,Ursula Le Guin\n\
+        octavia@spam.example,Octavia Butler\n\
+        ted@mailinator.com,Ted Chiang\n";
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed", "text/csv", body)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    let rejected = report["rejected"].as_array().unwrap();
+    assert_eq!(rejected.len(), 2);
+    assert_eq!(rejected[0]["row"], 2);
+    assert!(rejected[0]["reasons"][0].as_str().unwrap().contains("spam.example"));
+    assert_eq!(rejected[1]["row"], 3);
+    assert!(rejected[1]["reasons"][0].as_str().unwrap().contains("disposable"));
+}
+
+#[tokio::test]
+async fn a_json_import_stores_subscribers_with_the_requested_status() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let body = serde_json::json!([
+        {"email": "ursula@example.com", "name": "Ursula Le Guin"},
+        {"email": "ted@example.com"}
+    ]);
+
+    // Act
+    let response = app
+        .post_subscribers_import(
+            "status=pending_confirmation",
+            "application/json",
+            body.to_string()
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["rejected"][0]["row"], 2);
+    assert_eq!(app.get_subscriber_status().await, "pending_confirmation");
+    // importing does not send anything
+    assert!(app.email_server.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn subscribers_imported_as_pending_confirmation_get_a_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let body = serde_json::json!([{"email": "ursula_le_guin@gmail.com", "name": "le guin"}]);
+    app.post_subscribers_import("status=pending_confirmation", "application/json", body.to_string())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - The worker sends the queued email
+    app.dispatch_all_pending_emails().await;
+
+    // Act - Part 2 - Click on the confirmation link
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(app.get_subscriber_status().await, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribers_imported_as_confirmed_get_no_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let body = serde_json::json!([{"email": "ursula_le_guin@gmail.com", "name": "le guin"}]);
+    app.post_subscribers_import("status=confirmed", "application/json", body.to_string())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_tokens = sqlx::query!(r#"SELECT count(*) as "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn an_import_rejects_addresses_that_are_already_subscribed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    app.insert_subscriber("ursula@example.com", "Ursula Le Guin", "unsubscribed", chrono::Utc::now())
+        .await;
+    let body = "email,name\n\
+        ursula@example.com,Ursula Le Guin\n\
+        ted@example.com,Ted Chiang\n\
+        TED@Example.com,Ted Chiang\n";
+
+    // Act
+    let response = app
+        .post_subscribers_import("status=confirmed", "text/csv", body)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    let rejected_rows: Vec<_> = report["rejected"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["row"].as_u64().unwrap())
+        .collect();
+    assert_eq!(rejected_rows, vec![1, 3]);
+    // the existing subscriber is left alone
+    let status = sqlx::query!(
+        r#"
+        SELECT m.status
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        WHERE s.email = 'ursula@example.com'
+        "#
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn invalid_import_requests_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let test_cases = vec![
+        ("status=confirmed", "text/plain", "email,name\n", 415, "an unsupported content type"),
+        ("", "text/csv", "email,name\n", 400, "a missing status"),
+        ("status=subscribed", "text/csv", "email,name\n", 400, "an unknown status"),
+        ("status=confirmed", "text/csv", "mail,name\n", 400, "a CSV without an email column"),
+        ("status=confirmed", "application/json", "{}", 400, "JSON that is not an array")
+    ];
+
+    for (query, content_type, body, expected_status, description) in test_cases {
+        // Act
+        let response = app.post_subscribers_import(query, content_type, body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            expected_status,
+            "the API did not reject the import request with {}.",
+            description
+        );
+    }
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn subscribing_to_a_named_list_only_joins_that_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.insert_list("digest", "Weekly digest").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=digest".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.get_membership_statuses().await,
+        vec![("digest".to_string(), "pending_confirmation".to_string())]
+    );
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["TextBody"].as_str().unwrap().contains("Weekly digest"));
+
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        app.get_membership_statuses().await,
+        vec![("digest".to_string(), "confirmed".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=nope".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["invalid-params"][0]["name"], "list");
+    assert_eq!(problem["invalid-params"][0]["code"], "unknown_list");
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn joining_a_second_list_requires_its_own_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.insert_list("digest", "Weekly digest").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=digest".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        app.get_membership_statuses().await,
+        vec![
+            ("digest".to_string(), "pending_confirmation".to_string()),
+            ("newsletter".to_string(), "confirmed".to_string())
+        ]
+    );
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        app.get_membership_statuses().await,
+        vec![
+            ("digest".to_string(), "confirmed".to_string()),
+            ("newsletter".to_string(), "confirmed".to_string())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn issues_are_only_delivered_to_the_members_of_their_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.insert_list("digest", "Weekly digest").await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let mut body = newsletter_request_body();
+    body["list"] = "digest".into();
+
+    // Act
+    let response = app.post_newsletters(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut body = newsletter_request_body();
+    body["list"] = "nope".into();
+
+    // Act
+    let response = app.post_newsletters(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn unsubscribing_from_a_list_keeps_the_other_memberships() {
+    // Arrange
+    let app = spawn_app().await;
+    app.insert_list("digest", "Weekly digest").await;
+    let subscriber_id = app
+        .insert_subscriber("ursula@example.com", "Ursula Le Guin", "confirmed", chrono::Utc::now())
+        .await;
+    app.insert_membership(subscriber_id, "digest", "confirmed").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let mut body = newsletter_request_body();
+    body["list"] = "digest".into();
+    app.post_newsletters(body).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let unsubscribe_link = app.get_unsubscribe_link(&email_request);
+
+    // Act
+    let response = reqwest::Client::new().post(unsubscribe_link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        app.get_membership_statuses().await,
+        vec![
+            ("digest".to_string(), "unsubscribed".to_string()),
+            ("newsletter".to_string(), "confirmed".to_string())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn the_subscriber_listing_is_scoped_to_a_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    app.insert_list("digest", "Weekly digest").await;
+    let now = chrono::Utc::now();
+    let ursula_id = app
+        .insert_subscriber("ursula@example.com", "Ursula Le Guin", "confirmed", now)
+        .await;
+    app.insert_subscriber("octavia@example.com", "Octavia Butler", "confirmed", now).await;
+    app.insert_membership(ursula_id, "digest", "pending_confirmation").await;
+
+    // Act
+    let default_page: serde_json::Value = app.get_admin_subscribers("").await.json().await.unwrap();
+    let digest_page: serde_json::Value = app.get_admin_subscribers("list=digest").await.json().await.unwrap();
+
+    // Assert
+    assert_eq!(default_page["subscribers"].as_array().unwrap().len(), 2);
+    let digest_subscribers = digest_page["subscribers"].as_array().unwrap();
+    assert_eq!(digest_subscribers.len(), 1);
+    assert_eq!(digest_subscribers[0]["email"], "ursula@example.com");
+    assert_eq!(digest_subscribers[0]["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn importing_into_a_list_adds_subscribers_known_from_other_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    app.insert_list("digest", "Weekly digest").await;
+    app.insert_subscriber("ursula@example.com", "Ursula Le Guin", "confirmed", chrono::Utc::now())
+        .await;
+
+    // Act
+    let response = app
+        .post_subscribers_import(
+            "status=confirmed&list=digest",
+            "text/csv",
+            "email,name\nursula@example.com,Ursula Le Guin\n"
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(
+        app.get_membership_statuses().await,
+        vec![
+            ("digest".to_string(), "confirmed".to_string()),
+            ("newsletter".to_string(), "confirmed".to_string())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let get_response = app.get_admin_lists().await;
+    let post_response = app
+        .post_admin_lists(serde_json::json!({"slug": "digest", "name": "Weekly digest"}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&get_response, "/login");
+    assert_is_redirect_to(&post_response, "/login");
+}
+
+#[tokio::test]
+async fn lists_can_be_created_and_renamed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    // Act - Part 1 - Create
+    let response = app
+        .post_admin_lists(serde_json::json!({"slug": "digest", "name": "Weekly digest"}))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let list: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(list["slug"], "digest");
+    assert_eq!(list["name"], "Weekly digest");
+
+    // Act - Part 2 - Rename
+    let response = app
+        .patch_admin_list("digest", serde_json::json!({"name": "Monthly digest"}))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 3 - List
+    let response = app.get_admin_lists().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let lists = body["lists"].as_array().unwrap();
+    let slugs: Vec<_> = lists.iter().map(|l| l["slug"].as_str().unwrap()).collect();
+    assert_eq!(slugs, vec!["newsletter", "digest"]);
+    assert_eq!(lists[1]["name"], "Monthly digest");
+    assert_eq!(lists[1]["members"]["confirmed"], 0);
+}
+
+#[tokio::test]
+async fn the_list_summary_counts_members_by_status() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let now = chrono::Utc::now();
+    app.insert_subscriber("ursula@example.com", "Ursula Le Guin", "confirmed", now).await;
+    app.insert_subscriber("octavia@example.com", "Octavia Butler", "confirmed", now).await;
+    app.insert_subscriber("iain@example.com", "Iain M. Banks", "unsubscribed", now).await;
+
+    // Act
+    let body: serde_json::Value = app.get_admin_lists().await.json().await.unwrap();
+
+    // Assert
+    let members = &body["lists"][0]["members"];
+    assert_eq!(members["pending_confirmation"], 0);
+    assert_eq!(members["confirmed"], 2);
+    assert_eq!(members["unsubscribed"], 1);
+}
+
+#[tokio::test]
+async fn invalid_lists_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let test_cases = vec![
+        (serde_json::json!({"slug": "newsletter", "name": "Again"}), 409, "an existing slug"),
+        (serde_json::json!({"slug": "Weekly Digest", "name": "Weekly digest"}), 400, "an invalid slug"),
+        (serde_json::json!({"slug": "digest", "name": "  "}), 400, "an empty name")
+    ];
+
+    for (body, expected_status, description) in test_cases {
+        // Act
+        let response = app.post_admin_lists(body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            expected_status,
+            "the API did not reject a list with {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn renaming_an_unknown_list_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    // Act
+    let response = app
+        .patch_admin_list("nope", serde_json::json!({"name": "Weekly digest"}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn markdown_issues_are_delivered_as_html_and_plain_text() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "title": "Weekly digest",
+        "content": {
+            "markdown": "# Hello\n\nRead [the blog](https://example.com/blog).<script>alert(1)</script>"
+        }
+    });
+
+    // Act
+    let response = app.post_newsletters(body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = email["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("<title>Weekly digest</title>"));
+    assert!(html.contains("<h1>Hello</h1>"));
+    assert!(!html.contains("<script>"));
+    // the unsubscribe link stays inside the document
+    assert!(html.trim_end().ends_with("</html>"));
+    assert!(html.contains("Unsubscribe</a></p>\n</body>"));
+    let text = email["TextBody"].as_str().unwrap();
+    assert!(text.starts_with("Hello\n=====\n\nRead the blog (https://example.com/blog)."));
+}
+
+#[tokio::test]
+async fn previews_return_both_renderings_without_sending_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_as_test_user().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter_preview(serde_json::json!({
+            "title": "Weekly digest",
+            "content": {"markdown": "Some *news*."}
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let preview: serde_json::Value = response.json().await.unwrap();
+    assert!(preview["html"].as_str().unwrap().contains("<p>Some <em>news</em>.</p>"));
+    assert_eq!(preview["text"], "Some news.");
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_preview_an_issue() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletter_preview(serde_json::json!({
+            "title": "Weekly digest",
+            "content": {"markdown": "Some *news*."}
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_delivered_before_they_are_due() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_as_test_user().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(scheduled_newsletter_request_body()).await;
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["status"], "scheduled");
+    let scheduled: serde_json::Value = app.get_scheduled_issues().await.json().await.unwrap();
+    assert_eq!(scheduled["issues"][0]["issue_id"], issue["issue_id"]);
+    assert_eq!(scheduled["issues"][0]["list"], "newsletter");
+}
+
+#[tokio::test]
+async fn due_issues_are_delivered_exactly_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_as_test_user().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = app.schedule_issue().await;
+    app.make_issue_due(issue_id).await;
+
+    // Act - several schedulers racing for the same issue
+    let outcomes = tokio::join!(
+        try_enqueue_due_issue(&app.db_pool),
+        try_enqueue_due_issue(&app.db_pool),
+        try_enqueue_due_issue(&app.db_pool),
+        try_enqueue_due_issue(&app.db_pool)
+    );
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_enqueued = [outcomes.0, outcomes.1, outcomes.2, outcomes.3]
+        .into_iter()
+        .filter(|o| matches!(o, Ok(SchedulerOutcome::IssueEnqueued)))
+        .count();
+    assert_eq!(n_enqueued, 1);
+    let scheduled: serde_json::Value = app.get_scheduled_issues().await.json().await.unwrap();
+    assert_eq!(scheduled["issues"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut body = newsletter_request_body();
+    body["scheduled_at"] = serde_json::json!(chrono::Utc::now() - chrono::Duration::hours(1));
+
+    // Act
+    let response = app.post_newsletters(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let issue_id = app.schedule_issue().await;
+    let scheduled_at = chrono::Utc::now() + chrono::Duration::days(7);
+
+    // Act
+    let response = app
+        .patch_admin_newsletter(issue_id, serde_json::json!({"scheduled_at": scheduled_at}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["status"], "scheduled");
+    let rescheduled_at: chrono::DateTime<chrono::Utc> =
+        serde_json::from_value(issue["scheduled_at"].clone()).unwrap();
+    assert_eq!(rescheduled_at.timestamp_micros(), scheduled_at.timestamp_micros());
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_as_test_user().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = app.schedule_issue().await;
+
+    // Act
+    let response = app.post_cancel_newsletter(issue_id).await;
+    app.make_issue_due(issue_id).await;
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["status"], "cancelled");
+}
+
+#[tokio::test]
+async fn issues_that_started_sending_cannot_be_changed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let issue_id = app.schedule_issue().await;
+    app.make_issue_due(issue_id).await;
+    app.enqueue_due_issues().await;
+
+    // Act
+    let cancel_response = app.post_cancel_newsletter(issue_id).await;
+    let reschedule_response = app
+        .patch_admin_newsletter(
+            issue_id,
+            serde_json::json!({"scheduled_at": chrono::Utc::now() + chrono::Duration::days(1)})
+        )
+        .await;
+
+    // Assert
+    assert_eq!(cancel_response.status().as_u16(), 409);
+    assert_eq!(reschedule_response.status().as_u16(), 409);
+    let problem: serde_json::Value = cancel_response.json().await.unwrap();
+    assert_eq!(problem["detail"], "the issue is enqueued, it can no longer be changed.");
+}
+
+#[tokio::test]
+async fn changing_an_unknown_issue_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+
+    // Act
+    let response = app.post_cancel_newsletter(Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_scheduled_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = app.schedule_issue().await;
+
+    // Act
+    let list_response = app.get_scheduled_issues().await;
+    let cancel_response = app.post_cancel_newsletter(issue_id).await;
+
+    // Assert
+    assert_is_redirect_to(&list_response, "/login");
+    assert_is_redirect_to(&cancel_response, "/login");
+}
+
+fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
+}
+
+#[tokio::test]
+async fn the_seeded_admin_with_a_published_password_is_removed() {
+    let app = spawn_app().await;
+
+    let n_admins: Option<i64> = sqlx::query_scalar!("SELECT count(*) FROM users WHERE username = 'admin'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(n_admins, Some(0));
+}
+
+#[tokio::test]
+async fn admins_created_from_the_command_line_must_change_their_password() {
+    let app = spawn_app().await;
+    let password = Uuid::new_v4().to_string();
+    create_admin("ursula", Secret::new(password.clone()), &app.db_pool).await.unwrap();
+
+    let response = app.post_login(&serde_json::json!({ "username": "ursula", "password": &password })).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/admin/password");
+    let html_page = app.get_change_password_html().await;
+    assert!(html_page.contains("You must choose a new password before going any further."));
+
+    // the API refuses them as well
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth("ursula", Some(&password))
+        .header("Idempotency-Key", Uuid::new_v4().to_string())
+        .json(&serde_json::json!({ "title": "Newsletter title", "content": { "text": "text", "html": "<p>html</p>" } }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn changing_the_password_gives_access_to_the_admin_pages() {
+    let app = spawn_app().await;
+    let password = Uuid::new_v4().to_string();
+    create_admin("ursula", Secret::new(password.clone()), &app.db_pool).await.unwrap();
+    app.post_login(&serde_json::json!({ "username": "ursula", "password": &password })).await;
+    let new_password = Uuid::new_v4().to_string();
+
+    let response = app.post_change_password(&serde_json::json!({
+        "current_password": &password,
+        "new_password": &new_password,
+        "new_password_check": &new_password
+    }))
+    .await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+    app.post_logout().await;
+    let response = app.post_login(&serde_json::json!({ "username": "ursula", "password": &password })).await;
+    assert_is_redirect_to(&response, "/login");
+    app.post_login(&serde_json::json!({ "username": "ursula", "password": &new_password })).await;
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn invalid_password_changes_are_refused_with_a_reason() {
+    let app = spawn_app().await;
+    app.login_as_test_user().await;
+    let new_password = Uuid::new_v4().to_string();
+    let wrong_password = Uuid::new_v4().to_string();
+    let cases = [
+        (&app.test_user.password, new_password.as_str(), "another-long-password", "two different new passwords"),
+        (&app.test_user.password, "too-short", "too-short", "between 12 and 128 characters"),
+        (&app.test_user.password, app.test_user.password.as_str(), app.test_user.password.as_str(), "different from the current one"),
+        (&wrong_password, new_password.as_str(), new_password.as_str(), "current password is incorrect")
+    ];
+
+    for (current_password, new_password, new_password_check, message) in cases {
+        let response = app.post_change_password(&serde_json::json!({
+            "current_password": current_password,
+            "new_password": new_password,
+            "new_password_check": new_password_check
+        }))
+        .await;
+
+        assert_is_redirect_to(&response, "/admin/password");
+        assert!(app.get_change_password_html().await.contains(message), "{}", message);
+        // shown once only
+        assert!(!app.get_change_password_html().await.contains(message), "{}", message);
+    }
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = newsletter_request_body();
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Publish the newsletter
+    let response = app.post_newsletters_with_key(&body, &idempotency_key).await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    // Act - Part 2 - Publish the newsletter **again**
+    let response = app.post_newsletters_with_key(&body, &idempotency_key).await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    app.dispatch_all_pending_emails().await;
+
+    // the mock verifies on drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_submissions_are_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        // setting a long delay to ensure that the second request
+        // arrives before the first one completes
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = newsletter_request_body();
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - Submit two newsletter forms concurrently
+    let response1 = app.post_newsletters_with_key(&body, &idempotency_key);
+    let response2 = app.post_newsletters_with_key(&body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(response1.text().await.unwrap(), response2.text().await.unwrap());
+    app.dispatch_all_pending_emails().await;
+
+    // the mock verifies on drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn newsletters_without_an_idempotency_key_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn transient_delivery_failures_are_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let outcome = sqlx::query!("SELECT outcome, n_attempts FROM issue_delivery_outcomes")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch the delivery outcome");
+    assert_eq!(outcome.outcome, "delivered");
+    assert_eq!(outcome.n_attempts, 2);
+}
+
+#[tokio::test]
+async fn deliveries_failing_before_the_email_is_sent_are_retried_then_given_up() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_newsletters(newsletter_request_body()).await;
+    // the issue cannot be read anymore
+    sqlx::query("ALTER TABLE newsletter_issues RENAME COLUMN html_content TO html_body")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act - Part 1 - the first attempt fails
+    let outcome = try_execute_task(&app.db_pool, &app.email_client, &app.worker_settings, &app.base_url, &app.hmac_secret)
+        .await
+        .unwrap();
+
+    // Assert - Part 1 - the task is put back in the queue for later
+    assert!(matches!(outcome, ExecutionOutcome::TaskCompleted));
+    let task = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(task.n_retries, 1);
+
+    // Act - Part 2 - every other attempt fails as well
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 2
+    let outcome = sqlx::query!("SELECT outcome, n_attempts, last_error FROM issue_delivery_outcomes")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch the delivery outcome");
+    assert_eq!(outcome.outcome, "failed");
+    assert_eq!(outcome.n_attempts, app.worker_settings.max_attempts);
+    assert!(outcome.last_error.unwrap().contains("html_content"));
+    let n_queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+}
+
+#[tokio::test]
+async fn deliveries_are_given_up_after_the_maximum_number_of_attempts() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(app.worker_settings.max_attempts as u64)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let outcome = sqlx::query!("SELECT outcome, n_attempts FROM issue_delivery_outcomes")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch the delivery outcome");
+    assert_eq!(outcome.outcome, "failed");
+    assert_eq!(outcome.n_attempts, app.worker_settings.max_attempts);
+    let n_queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_not_retried_before_their_backoff_expires() {
+    // Arrange
+    let mut app = spawn_app().await;
+    app.worker_settings.base_backoff_milliseconds = 60 * 1000;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!(
+        r#"SELECT n_retries, execute_after > now() as "in_the_future!" FROM issue_delivery_queue"#
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("the task should still be queued");
+    assert_eq!(task.n_retries, 1);
+    assert!(task.in_the_future);
+}
+
+#[tokio::test]
+async fn issues_carry_one_click_list_unsubscribe_headers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let unsubscribe_link = app.publish_and_get_unsubscribe_link().await;
+
+    // Assert
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.contains(&serde_json::json!({
+        "Name": "List-Unsubscribe-Post",
+        "Value": "List-Unsubscribe=One-Click"
+    })));
+    assert_eq!(unsubscribe_link.path(), "/subscriptions/unsubscribe");
+    // the link is also part of the content, for clients ignoring the headers
+    let token = unsubscribe_link.query().unwrap();
+    assert!(body["TextBody"].as_str().unwrap().contains(token));
+    assert!(body["HtmlBody"].as_str().unwrap().contains(token));
+}
+
+#[tokio::test]
+async fn a_one_click_unsubscribe_request_unsubscribes_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = app.publish_and_get_unsubscribe_link().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.get_subscriber_status().await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn visiting_the_unsubscribe_link_asks_for_a_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = app.publish_and_get_unsubscribe_link().await;
+
+    // Act
+    let response = reqwest::get(unsubscribe_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"method="post""#));
+    // prefetching the link must not unsubscribe anybody
+    assert_eq!(app.get_subscriber_status().await, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribe_requests_with_a_forged_token_are_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let membership = sqlx::query!("SELECT subscriber_id, list_id FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let forged_token = UnsubscribeToken::generate(
+        membership.subscriber_id,
+        membership.list_id,
+        &secrecy::Secret::new("not-our-secret".into())
+    );
+    let url = format!(
+        "{}/subscriptions/unsubscribe?token={}",
+        app.address,
+        forged_token.as_ref()
+    );
+
+    // Act
+    let get_response = reqwest::get(&url).await.unwrap();
+    let post_response = reqwest::Client::new().post(&url).send().await.unwrap();
+
+    // Assert
+    assert_eq!(get_response.status().as_u16(), 401);
+    assert_eq!(post_response.status().as_u16(), 401);
+    assert_eq!(app.get_subscriber_status().await, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_later_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = app.publish_and_get_unsubscribe_link().await;
+    reqwest::Client::new()
+        .post(unsubscribe_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn queued_issues_are_skipped_if_the_subscriber_unsubscribes_before_delivery() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let membership = sqlx::query!("SELECT subscriber_id, list_id FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+
+    // Act
+    let token = UnsubscribeToken::generate(membership.subscriber_id, membership.list_id, &app.hmac_secret.0);
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions/unsubscribe?token={}", app.address, token.as_ref()))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let outcome = sqlx::query!("SELECT outcome FROM issue_delivery_outcomes")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch the delivery outcome");
+    assert_eq!(outcome.outcome, "skipped");
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+/// Use the public API of the application under test to create
+/// a subscriber that has not clicked on the confirmation link yet.
+fn scheduled_newsletter_request_body() -> serde_json::Value {
+    let mut body = newsletter_request_body();
+    body["scheduled_at"] = serde_json::json!(chrono::Utc::now() + chrono::Duration::days(1));
+    body
+}
+
+async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+//  Ensure that the tracing stack is only initlalised once using once_cell
+static TRACING: Lazy<()> = Lazy::new(|| {
+    let default_filter_level = "info".to_string();
+    let subscriber_name = "test".to_string();
+
+    // We cannot assign the output of get_subscriber to a variable based on the value of TEST_LOG
+    // because the sink is part of the type returned by get_subscriber, therefore they are not the
+    // same type, We could work around it, but this is the most straight-forward way of moving forward.
+
+    if std::env::var("TEST_LOG").is_ok() {
+        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
+        init_subscriber(subscriber);
+    } else {
+        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
+        init_subscriber(subscriber);
+    }
+});
+
+pub struct TestApp {
+    pub address: String,
+    pub port: u16,
+    // where the metrics are served
+    pub admin_address: String,
+    pub db_pool: PgPool,
+    pub email_server: MockServer,
+    pub test_user: TestUser,
+    // keeps the session cookie between requests, never follows redirects
+    pub api_client: reqwest::Client,
+    pub email_client: EmailClient,
+    pub worker_settings: WorkerSettings,
+    pub base_url: ApplicationBaseUrl,
+    pub hmac_secret: HmacSecret
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string()
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        // match the parameters of the production hashes,
+        // the exact values do not matter for the tests
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap()
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash
+            )
+            .execute(pool)
+            .await
+            .expect("failed to store test user");
+    }
+}
+
+/// Confirmation links embedded in the request to the email API.
+pub struct ConfirmationLinks {
+    pub html: reqwest::Url,
+    pub plain_text: reqwest::Url
+}
+
+impl TestApp {
+    /// Drain the confirmation email and delivery queues, as the background worker would.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_send_confirmation_email(
+                    &self.db_pool,
+                    &self.email_client,
+                    &self.worker_settings,
+                    &self.base_url
+                )
+                .await
+                .unwrap()
+            {
+                break;
+            }
+        }
+        loop {
+            if let ExecutionOutcome::EmptyQueue =
+                try_execute_task(
+                    &self.db_pool,
+                    &self.email_client,
+                    &self.worker_settings,
+                    &self.base_url,
+                    &self.hmac_secret
+                )
+                .await
+                .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Queue every due issue, as the scheduler would.
+    pub async fn enqueue_due_issues(&self) {
+        loop {
+            if let SchedulerOutcome::NothingDue = try_enqueue_due_issue(&self.db_pool).await.unwrap() {
+                break;
+            }
+        }
+    }
+
+    /// Schedule an issue for tomorrow, returning its id.
+    pub async fn schedule_issue(&self) -> Uuid {
+        let response = self.post_newsletters(scheduled_newsletter_request_body()).await;
+        assert_eq!(response.status().as_u16(), 202);
+        let issue: serde_json::Value = response.json().await.unwrap();
+        issue["issue_id"].as_str().unwrap().parse().unwrap()
+    }
+
+    /// Move the schedule of an issue to the past, without waiting for it.
+    pub async fn make_issue_due(&self, issue_id: Uuid) {
+        sqlx::query!(
+            "UPDATE newsletter_issues SET scheduled_at = now() - interval '1 minute' WHERE newsletter_issue_id = $1",
+            issue_id
+        )
+        .execute(&self.db_pool)
+        .await
+        .unwrap();
+    }
+
+    pub async fn get_metrics(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/metrics", &self.admin_address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    // the counters are shared by every app of the test process: compare values before and after
+    pub async fn metric_value(&self, series: &str) -> f64 {
+        let body = self.get_metrics().await.text().await.unwrap();
+        body.lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .map(|value| value.parse().unwrap())
+            .unwrap_or(0.0)
+    }
+
+    pub async fn get_health(&self, probe: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/health/{}", &self.address, probe))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    // as if a delivery worker and a scheduler had just gone through their loops
+    pub async fn record_worker_heartbeats(&self) {
+        for kind in ["delivery_worker", "issue_scheduler"] {
+            sqlx::query!(
+                "INSERT INTO worker_heartbeats (worker_id, kind, started_at, last_beat_at) VALUES ($1, $2, now(), now())",
+                Uuid::new_v4(),
+                kind
+            )
+            .execute(&self.db_pool)
+            .await
+            .unwrap();
+        }
+    }
+
+    pub async fn get_scheduled_issues(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/newsletters/scheduled", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn patch_admin_newsletter(&self, issue_id: Uuid, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .patch(format!("{}/admin/newsletters/{}", &self.address, issue_id))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_cancel_newsletter(&self, issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters/{}/cancel", &self.address, issue_id))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn login_as_test_user(&self) -> reqwest::Response {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password
+        }))
+        .await
+    }
+
+    pub async fn post_newsletters_with_key(
+        &self,
+        body: &serde_json::Value,
+        idempotency_key: &str
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", Uuid::new_v4().to_string())
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// Extract the confirmation links from the body of an email request
+    /// captured by the mock email server.
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+        let get_link = |s: &str| {
+            let links: Vec<_> = linkify::LinkFinder::new()
+                .links(s)
+                .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                .collect();
+            assert_eq!(links.len(), 1);
+            let raw_link = links[0].as_str().to_owned();
+            let mut confirmation_link = reqwest::Url::parse(&raw_link).unwrap();
+            // make sure we don't call random APIs on the web
+            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
+            // the base url in the configuration does not know the random port
+            confirmation_link.set_port(Some(self.port)).unwrap();
+            confirmation_link
+        };
+
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
+        ConfirmationLinks { html, plain_text }
+    }
+
+    /// Extract the link from the `List-Unsubscribe` header
+    /// of an issue captured by the mock email server.
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> reqwest::Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let header = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .expect("the issue has no List-Unsubscribe header");
+        let raw_link = header["Value"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        let mut unsubscribe_link = reqwest::Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
+    /// Publish an issue to the confirmed subscribers and return
+    /// the unsubscribe link received by the (single) subscriber.
+    pub async fn publish_and_get_unsubscribe_link(&self) -> reqwest::Url {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_newsletters(newsletter_request_body()).await;
+        self.dispatch_all_pending_emails().await;
+
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        self.get_unsubscribe_link(&email_request)
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers/export?{}", &self.address, query))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_subscribers_import(
+        &self,
+        query: &str,
+        content_type: &str,
+        body: impl Into<reqwest::Body>
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/subscribers/import?{}", &self.address, query))
+            .header("Content-Type", content_type)
+            .body(body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// Store a subscriber of the default list straight in the database,
+    /// to control fields the public API does not let us choose.
+    pub async fn insert_subscriber(
+        &self,
+        email: &str,
+        name: &str,
+        status: &str,
+        subscribed_at: chrono::DateTime<chrono::Utc>
+    ) -> Uuid {
+        let subscriber_id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, email_canonical, name, subscribed_at)
+            VALUES ($1, $2, lower($2), $3, $4)
+            "#,
+            subscriber_id,
+            email,
+            name,
+            subscribed_at
+            )
+            .execute(&self.db_pool)
+            .await
+            .expect("failed to insert subscriber");
+        sqlx::query!(
+            r#"
+            INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+            SELECT list_id, $1, $2, $3 FROM lists WHERE slug = 'newsletter'
+            "#,
+            subscriber_id,
+            status,
+            subscribed_at
+            )
+            .execute(&self.db_pool)
+            .await
+            .expect("failed to add the subscriber to the default list");
+        subscriber_id
+    }
+
+    pub async fn post_newsletter_preview(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters/preview", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_admin_lists(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/lists", &self.address))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_admin_lists(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn patch_admin_list(&self, slug: &str, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .patch(format!("{}/admin/lists/{}", &self.address, slug))
+            .json(&body)
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    /// Create a list straight in the database.
+    pub async fn insert_list(&self, slug: &str, name: &str) {
+        sqlx::query!(
+            r#"
+            INSERT INTO lists (list_id, slug, name, created_at)
+            VALUES ($1, $2, $3, now())
+            "#,
+            Uuid::new_v4(),
+            slug,
+            name
+            )
+            .execute(&self.db_pool)
+            .await
+            .expect("failed to insert list");
+    }
+
+    /// Add an existing subscriber to a list straight in the database.
+    pub async fn insert_membership(&self, subscriber_id: Uuid, slug: &str, status: &str) {
+        sqlx::query!(
+            r#"
+            INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+            SELECT list_id, $1, $3, now() FROM lists WHERE slug = $2
+            "#,
+            subscriber_id,
+            slug,
+            status
+            )
+            .execute(&self.db_pool)
+            .await
+            .expect("failed to add the subscriber to the list");
+    }
+
+    /// Status of the (single) subscriber in each of their lists, by list slug.
+    pub async fn get_membership_statuses(&self) -> Vec<(String, String)> {
+        sqlx::query!(
+            r#"
+            SELECT l.slug, m.status
+            FROM list_memberships m
+            JOIN lists l ON l.list_id = m.list_id
+            ORDER BY l.slug
+            "#
+            )
+            .fetch_all(&self.db_pool)
+            .await
+            .expect("failed to fetch the list memberships")
+            .into_iter()
+            .map(|r| (r.slug, r.status))
+            .collect()
+    }
+
+    pub async fn get_subscriber_status(&self) -> String {
+        sqlx::query!("SELECT status FROM list_memberships")
+            .fetch_one(&self.db_pool)
+            .await
+            .expect("failed to fetch the subscription")
+            .status
+    }
+}
+
+// No .await call, therefore no need for `spawn_app` to be async now.
+// We are also running tests, so it is not worth it to propagate errors:
+// if we fail to perform the required setup we can just panic and crash
+// all the things.
+async fn spawn_app() -> TestApp {
+    spawn_app_with_configuration(|_| {}).await
+}
+
+/// Spawn the application with a configuration tweaked by the test.
+fn set_email_api_url(configuration: &mut Settings, base_url: String) {
+    match &mut configuration.email_client.transport {
+        EmailTransportSettings::Http(http) => http.base_url = base_url,
+        EmailTransportSettings::Smtp(_) => panic!("the tests expect the http transport")
+    }
+}
+
+async fn spawn_app_with_configuration(customise: impl FnOnce(&mut Settings)) -> TestApp {
+    //  The first time initialize is invoked the code in TRACING is executed
+    //  All other invocation will instead skip execution
+    Lazy::force(&TRACING);
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+    .expect("Failed to bind random port");
+
+    // launch a mock server to stand in for the email API
+    let email_server = MockServer::start().await;
+
+    let mut configuration = get_configuration().expect("failed to read configuration");
+    configuration.database.database_name = Uuid::new_v4().to_string();
+    set_email_api_url(&mut configuration, email_server.uri());
+
+    // the 'Connection' trait MUST be in scope for us to invoke
+    // 'PgConnection::connect' - it is not an inherent method of the struct!
+    let connection_pool = configure_database(&configuration.database).await;
+
+    // We retrieve the port assigned to us by the OS
+    let port = listener.local_addr().unwrap().port();
+    // retries are executed right away, unless a test asks otherwise
+    configuration.worker.max_attempts = 3;
+    configuration.worker.base_backoff_milliseconds = 0;
+    customise(&mut configuration);
+
+    let email_client = configuration.email_client.clone().client()
+        .expect("invalid email client configuration");
+
+    let server = zero2prod::startup::run(
+            listener,
+            connection_pool.clone(),
+            email_client,
+            configuration.application.base_url.clone(),
+            configuration.application.hmac_secret.clone(),
+            configuration.issues.renderer().expect("invalid issue layout"),
+            configuration.rate_limiting.limiter().expect("invalid rate limiting configuration"),
+            configuration.signup_protection.protection(),
+            configuration.domain_policy.policy().expect("invalid domain policy")
+        ).expect("Failed to bind address");
+    let address = format!("http://127.0.0.1:{}", port);
+    drop(tokio::spawn(server));
+
+    let admin_listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
+    let admin_address = format!("http://127.0.0.1:{}", admin_listener.local_addr().unwrap().port());
+    let admin_server = zero2prod::startup::run_admin(admin_listener, Some(connection_pool.clone()))
+        .expect("Failed to bind address");
+    drop(tokio::spawn(admin_server));
+
+    let test_user = TestUser::generate();
+    test_user.store(&connection_pool).await;
+
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
 
     // We return the application address to the caller!
     TestApp {
         address,
-        db_pool: connection_pool
+        port,
+        admin_address,
+        db_pool: connection_pool,
+        email_server,
+        test_user,
+        api_client,
+        email_client: configuration.email_client.client().unwrap(),
+        worker_settings: configuration.worker,
+        base_url: ApplicationBaseUrl(configuration.application.base_url),
+        hmac_secret: HmacSecret(configuration.application.hmac_secret)
     }
 
 }